@@ -5,6 +5,7 @@ use chrono::{DateTime, Utc};
 pub use middleware::{TokenVerify, set_layer, verify_token};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use sqlx::types::Json;
 pub use utils::*;
 use utoipa::ToSchema;
 
@@ -54,6 +55,64 @@ pub enum ChatType {
     PublicChannel,
 }
 
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ChatInvite {
+    pub id: i64,
+    #[serde(alias = "chatId")]
+    pub chat_id: i64,
+    pub code: String,
+    #[serde(alias = "createdBy")]
+    pub created_by: i64,
+    #[serde(alias = "expiresAt")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(alias = "maxUses")]
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    #[serde(alias = "revokedAt")]
+    pub revoked_at: Option<DateTime<Utc>>,
+    #[serde(alias = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Default, ToSchema, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "message_format", rename_all = "snake_case")]
+#[serde(rename_all(serialize = "camelCase"))]
+pub enum ContentFormat {
+    #[default]
+    #[serde(alias = "plain", alias = "Plain")]
+    Plain,
+    #[serde(alias = "markdown", alias = "Markdown")]
+    Markdown,
+}
+
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "chat_role", rename_all = "snake_case")]
+#[serde(rename_all(serialize = "camelCase"))]
+pub enum ChatRole {
+    #[serde(alias = "owner", alias = "Owner")]
+    Owner,
+    #[serde(alias = "admin", alias = "Admin")]
+    Admin,
+    #[serde(alias = "member", alias = "Member")]
+    Member,
+}
+
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ChatMember {
+    #[serde(alias = "userId")]
+    pub user_id: i64,
+    pub fullname: String,
+    pub email: String,
+    pub role: ChatRole,
+    #[serde(alias = "joinedAt")]
+    pub joined_at: DateTime<Utc>,
+    /// who added the member, unknown for members from before roles existed
+    #[serde(alias = "addedBy")]
+    pub added_by: Option<i64>,
+}
+
 #[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all(serialize = "camelCase"))]
 pub struct Chat {
@@ -65,6 +124,26 @@ pub struct Chat {
     pub members: Vec<i64>,
     #[serde(alias = "createdAt")]
     pub created_at: DateTime<Utc>,
+    #[sqlx(default)]
+    #[serde(default, alias = "unreadCount")]
+    pub unread_count: i64,
+    #[sqlx(default)]
+    #[serde(alias = "lastReadId")]
+    pub last_read_id: Option<i64>,
+    #[sqlx(default)]
+    #[serde(default, alias = "pinCount")]
+    pub pin_count: i64,
+    /// seconds before new messages of the chat disappear
+    #[serde(alias = "messageTtl")]
+    pub message_ttl: Option<i32>,
+    /// archived chats are read only, members can still leave or be removed until it is unarchived
+    #[serde(default, alias = "archivedAt")]
+    pub archived_at: Option<DateTime<Utc>>,
+    /// unsent draft of the user, only filled when listing the chats
+    #[sqlx(default)]
+    #[serde(default)]
+    #[schema(value_type = Option<Draft>)]
+    pub draft: Option<Json<Draft>>,
 }
 
 #[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
@@ -79,6 +158,204 @@ pub struct Message {
     pub files: Vec<String>,
     #[serde(alias = "createdAt")]
     pub created_at: DateTime<Utc>,
+    #[serde(alias = "updatedAt")]
+    pub updated_at: Option<DateTime<Utc>>,
+    #[serde(alias = "deletedAt")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    #[serde(alias = "parentId")]
+    pub parent_id: Option<i64>,
+    #[serde(alias = "expiresAt")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[schema(value_type = Option<MessageQuote>)]
+    pub quote: Option<Json<MessageQuote>>,
+    pub format: ContentFormat,
+    /// sanitized html rendering of the content, empty in notifications
+    #[serde(default)]
+    pub html: String,
+    /// plain text fallback of the content, empty in notifications
+    #[serde(default, alias = "plainText")]
+    pub plain_text: String,
+    /// nonce the sender chose to dedupe retries, only set when the message is created
+    #[sqlx(default)]
+    #[serde(default, alias = "clientNonce")]
+    pub client_nonce: Option<String>,
+    #[sqlx(default)]
+    #[serde(default, alias = "replyCount")]
+    pub reply_count: i64,
+    #[sqlx(default)]
+    #[serde(alias = "lastReplyAt")]
+    pub last_reply_at: Option<DateTime<Utc>>,
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub poll: Option<Poll>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Poll {
+    pub question: String,
+    pub options: Vec<PollOption>,
+    /// whether a user can choose more than one option
+    pub multiple: bool,
+    pub anonymous: bool,
+    #[serde(alias = "closesAt")]
+    pub closes_at: Option<DateTime<Utc>>,
+    /// number of users who voted
+    pub voters: i64,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct PollOption {
+    pub text: String,
+    pub count: i64,
+    /// who chose this option, always empty for an anonymous poll
+    pub voters: Vec<i64>,
+    /// whether the current user chose this option
+    pub me: bool,
+}
+
+/// Snapshot of a quoted or forwarded message, taken when the quoting message is sent.
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct MessageQuote {
+    #[serde(alias = "messageId")]
+    pub message_id: i64,
+    #[serde(alias = "chatId")]
+    pub chat_id: i64,
+    #[serde(alias = "senderId")]
+    pub sender_id: i64,
+    #[serde(alias = "senderName")]
+    pub sender_name: String,
+    pub content: String,
+    pub files: Vec<String>,
+    #[serde(alias = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: i64,
+    /// whether the current user reacted with this emoji
+    pub me: bool,
+}
+
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct MessageRevision {
+    pub id: i64,
+    #[serde(alias = "messageId")]
+    pub message_id: i64,
+    pub content: String,
+    pub files: Vec<String>,
+    #[serde(alias = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Draft {
+    #[serde(alias = "chatId")]
+    pub chat_id: i64,
+    #[serde(alias = "userId")]
+    pub user_id: i64,
+    pub content: String,
+    pub files: Vec<String>,
+    #[serde(alias = "updatedAt")]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ReadReceipt {
+    #[serde(alias = "chatId")]
+    pub chat_id: i64,
+    #[serde(alias = "userId")]
+    pub user_id: i64,
+    #[serde(alias = "lastReadMessageId")]
+    pub last_read_message_id: i64,
+    #[serde(alias = "updatedAt")]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Pin {
+    #[serde(alias = "pinnedBy")]
+    pub pinned_by: i64,
+    #[serde(alias = "pinnedAt")]
+    pub pinned_at: DateTime<Utc>,
+    #[sqlx(flatten)]
+    pub message: Message,
+}
+
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ScheduledMessage {
+    pub id: i64,
+    #[serde(alias = "chatId")]
+    pub chat_id: i64,
+    #[serde(alias = "senderId")]
+    pub sender_id: i64,
+    pub content: String,
+    pub format: ContentFormat,
+    pub files: Vec<String>,
+    #[serde(alias = "parentId")]
+    pub parent_id: Option<i64>,
+    #[serde(alias = "quoteOf")]
+    pub quote_of: Option<i64>,
+    #[serde(alias = "sendAt")]
+    pub send_at: DateTime<Utc>,
+    #[serde(alias = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    /// set when the message could not be sent, rescheduling retries it
+    #[serde(alias = "failedAt")]
+    pub failed_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+    /// nonce the sender chose to dedupe retries
+    #[serde(default, alias = "clientNonce")]
+    pub client_nonce: Option<String>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct SavedMessage {
+    pub id: i64,
+    pub note: Option<String>,
+    #[serde(alias = "remindAt")]
+    pub remind_at: Option<DateTime<Utc>>,
+    #[serde(alias = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(alias = "chatName")]
+    pub chat_name: Option<String>,
+    #[serde(alias = "chatType")]
+    pub chat_type: ChatType,
+    pub message: Message,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "mention_kind", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum MentionKind {
+    User,
+    /// mentions every member of the chat, the same as `Channel`
+    Here,
+    Channel,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Mention {
+    pub id: i64,
+    pub kind: MentionKind,
+    #[serde(alias = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    pub message: Message,
 }
 
 #[cfg(test)]
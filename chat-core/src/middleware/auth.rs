@@ -30,8 +30,8 @@ where
     let option = headers.typed_get::<Authorization<Bearer>>();
     let token = if let Some(Authorization(bearer)) = option {
         bearer.token().to_string()
-    } else if params.token.is_some() {
-        params.token.unwrap()
+    } else if let Some(token) = params.token {
+        token
     } else {
         let msg = "missing Authorization header";
         warn!(msg);
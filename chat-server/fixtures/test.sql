@@ -14,14 +14,30 @@ VALUES (1, 'wu@github.org', 'Jim Wu', '$argon2id$v=19$m=19456,t=2,p=1$MxGhY+ib/k
 
 -- insert 4 chats
 -- insert public/private channel
-INSERT INTO chats(ws_id, name, type, members)
-VALUES (1, 'general', 'public_channel', '{1,2,3,4,5}'),
-       (1, 'private', 'private_channel', '{1,2,3}');
+INSERT INTO chats(ws_id, name, type)
+VALUES (1, 'general', 'public_channel'),
+       (1, 'private', 'private_channel');
 
 -- insert unnamed chat
-INSERT INTO chats(ws_id, type, members)
-VALUES (1, 'single', '{1,2}'),
-       (1, 'group', '{1,3,4}');
+INSERT INTO chats(ws_id, type)
+VALUES (1, 'single'),
+       (1, 'group');
+
+-- insert chat members, wu owns all the chats
+INSERT INTO chat_members(chat_id, user_id, role)
+VALUES (1, 1, 'owner'),
+       (1, 2, 'member'),
+       (1, 3, 'member'),
+       (1, 4, 'member'),
+       (1, 5, 'member'),
+       (2, 1, 'owner'),
+       (2, 2, 'member'),
+       (2, 3, 'member'),
+       (3, 1, 'owner'),
+       (3, 2, 'member'),
+       (4, 1, 'owner'),
+       (4, 3, 'member'),
+       (4, 4, 'member');
 
 
 INSERT INTO messages(chat_id, sender_id, content)
@@ -35,6 +35,39 @@ pub enum AppError {
     #[error("update chat error: {0}")]
     UpdateChatError(String),
 
+    #[error("update message error: {0}")]
+    UpdateMessageError(String),
+
+    #[error("reaction error: {0}")]
+    ReactionError(String),
+
+    #[error("content error: {0}")]
+    ContentError(String),
+
+    #[error("cursor error: {0}")]
+    CursorError(String),
+
+    #[error("draft error: {0}")]
+    DraftError(String),
+
+    #[error("poll error: {0}")]
+    PollError(String),
+
+    #[error("invite error: {0}")]
+    InviteError(String),
+
+    #[error("pin error: {0}")]
+    PinError(String),
+
+    #[error("search error: {0}")]
+    SearchError(String),
+
+    #[error("read error: {0}")]
+    ReadError(String),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
     #[error("Not found: {0}")]
     NotFound(String),
 }
@@ -53,6 +86,17 @@ impl IntoResponse for AppError {
             AppError::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::ChatFileError(_) => StatusCode::BAD_REQUEST,
             AppError::CreateMessageError(_) => StatusCode::BAD_REQUEST,
+            AppError::UpdateMessageError(_) => StatusCode::BAD_REQUEST,
+            AppError::ReactionError(_) => StatusCode::BAD_REQUEST,
+            AppError::ContentError(_) => StatusCode::BAD_REQUEST,
+            AppError::CursorError(_) => StatusCode::BAD_REQUEST,
+            AppError::DraftError(_) => StatusCode::BAD_REQUEST,
+            AppError::PollError(_) => StatusCode::BAD_REQUEST,
+            AppError::InviteError(_) => StatusCode::BAD_REQUEST,
+            AppError::PinError(_) => StatusCode::BAD_REQUEST,
+            AppError::SearchError(_) => StatusCode::BAD_REQUEST,
+            AppError::ReadError(_) => StatusCode::BAD_REQUEST,
+            AppError::PermissionDenied(_) => StatusCode::FORBIDDEN,
         };
         (status, Json(ErrorOutput::new(self.to_string()))).into_response()
     }
@@ -1,10 +1,13 @@
-use crate::model::{CreateChat, UpdateChat};
+use crate::model::{
+    ChannelInfo, CreateChat, CreateInvite, ListChannels, ListMembers, MarkRead, SaveDraft,
+    UpdateChat, UpdateMember,
+};
 use crate::{AppError, AppState, ErrorOutput};
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::{Extension, Json};
-use chat_core::{Chat, User};
+use chat_core::{Chat, ChatInvite, ChatMember, Draft, ReadReceipt, User};
 
 /// List all chats in the workspace of the user.
 #[utoipa::path(
@@ -26,6 +29,28 @@ pub(crate) async fn list_chat_handler(
     Ok((StatusCode::OK, Json(chat)))
 }
 
+/// List the archived chats of the user.
+#[utoipa::path(
+    get,
+    path = "/api/chats/archived",
+    responses(
+         (status = 200, description = "List of archived chats", body = Vec<Chat>),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn list_archived_chats_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let chats = state
+        .fetch_archived_chats(user.id as _, user.ws_id as _)
+        .await?;
+    Ok(Json(chats))
+}
+
 /// Create a new chat in the workspace of the user.
 #[utoipa::path(
     post,
@@ -49,6 +74,83 @@ pub(crate) async fn create_chat_handler(
     Ok((StatusCode::CREATED, Json(chat)))
 }
 
+/// List the public channels of the workspace, optionally searched by name.
+#[utoipa::path(
+    get,
+    path = "/api/channels",
+    params(
+         ListChannels
+    ),
+    responses(
+         (status = 200, description = "List of public channels", body = Vec<ChannelInfo>),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn list_channels_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListChannels>,
+) -> Result<impl IntoResponse, AppError> {
+    let channels = state
+        .list_channels(input, user.id as _, user.ws_id as _)
+        .await?;
+    Ok(Json(channels))
+}
+
+/// Join a public channel of the workspace.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/join",
+    params(
+         ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+         (status = 200, description = "Chat joined", body = Chat),
+         (status = 403, description = "Not a public channel", body = ErrorOutput),
+         (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn join_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.join_chat(id, user.id as _).await?;
+    Ok(Json(chat))
+}
+
+/// Leave the chat, a single chat can not be left.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/leave",
+    params(
+         ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+         (status = 204, description = "Chat left"),
+         (status = 400, description = "Chat can not be left", body = ErrorOutput),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn leave_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.leave_chat(id, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 /// Get the chat info by id.
 #[utoipa::path(
     get,
@@ -85,6 +187,7 @@ pub(crate) async fn get_chat_handler(
     request_body(content = UpdateChat, description = "update chat", content_type = "application/json"),
     responses(
          (status = 200, description = "Chat found", body = Chat),
+         (status = 403, description = "Not a chat admin", body = ErrorOutput),
          (status = 404, description = "Chat not found", body = ErrorOutput),
     ),
     tag="chat",
@@ -93,11 +196,17 @@ pub(crate) async fn get_chat_handler(
     )
 )]
 pub(crate) async fn update_chat_handler(
+    Extension(user): Extension<User>,
     State(state): State<AppState>,
     Path(id): Path<u64>,
     Json(input): Json<UpdateChat>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = state.update_chat_by_id(id, input).await?;
+    if !state.is_chat_admin(id, user.id as _).await? {
+        return Err(AppError::PermissionDenied(
+            "Only a chat admin can update the chat".to_string(),
+        ));
+    }
+    let chat = state.update_chat_by_id(id, input, user.id as _).await?;
     Ok(Json(chat))
 }
 
@@ -109,6 +218,7 @@ pub(crate) async fn update_chat_handler(
     ),
     responses(
          (status = 200, description = "Chat deleted"),
+         (status = 403, description = "Not a chat admin", body = ErrorOutput),
     ),
     tag="chat",
     security(
@@ -116,9 +226,476 @@ pub(crate) async fn update_chat_handler(
     )
 )]
 pub(crate) async fn delete_chat_handler(
+    Extension(user): Extension<User>,
     State(state): State<AppState>,
     Path(id): Path<u64>,
 ) -> Result<impl IntoResponse, AppError> {
+    if !state.is_chat_admin(id, user.id as _).await? {
+        return Err(AppError::PermissionDenied(
+            "Only a chat admin can delete the chat".to_string(),
+        ));
+    }
     state.delete_chat_by_id(id).await?;
     Ok(StatusCode::OK)
 }
+
+/// Mark the chat as read up to a message, defaults to the latest message.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/read",
+    params(
+         ("id" = u64, Path, description = "Chat id")
+    ),
+    request_body(content = MarkRead, description = "mark read", content_type = "application/json"),
+    responses(
+         (status = 200, description = "Read position", body = ReadReceipt),
+         (status = 400, description = "Chat has no messages", body = ErrorOutput),
+         (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn mark_read_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<MarkRead>,
+) -> Result<impl IntoResponse, AppError> {
+    let receipt = state.mark_read(input, id, user.id as _).await?;
+    Ok(Json(receipt))
+}
+
+/// Get the draft of the user in the chat.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/draft",
+    params(
+         ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+         (status = 200, description = "Draft of the user", body = Draft),
+         (status = 404, description = "No draft", body = ErrorOutput),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn get_draft_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    match state.get_draft(id, user.id as _).await? {
+        Some(draft) => Ok(Json(draft)),
+        None => Err(AppError::NotFound(format!("draft of chat id {id}"))),
+    }
+}
+
+/// Save the draft of the user in the chat, the other sessions of the user are notified.
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/draft",
+    params(
+         ("id" = u64, Path, description = "Chat id")
+    ),
+    request_body(content = SaveDraft, description = "save draft", content_type = "application/json"),
+    responses(
+         (status = 200, description = "Draft saved", body = Draft),
+         (status = 400, description = "Invalid draft", body = ErrorOutput),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn save_draft_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<SaveDraft>,
+) -> Result<impl IntoResponse, AppError> {
+    let draft = state.save_draft(input, id, user.id as _).await?;
+    Ok(Json(draft))
+}
+
+/// Discard the draft of the user in the chat.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/draft",
+    params(
+         ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+         (status = 204, description = "Draft discarded"),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn delete_draft_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_draft(id, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// List the members of the chat with their roles.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/members",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ListMembers
+    ),
+    responses(
+         (status = 200, description = "List of chat members", body = Vec<ChatMember>),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn list_chat_members_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Query(input): Query<ListMembers>,
+) -> Result<impl IntoResponse, AppError> {
+    let members = state.list_chat_members(id, input).await?;
+    Ok(Json(members))
+}
+
+/// Add a user of the workspace to the chat, only chat admins can add members.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/members/{uid}",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("uid" = u64, Path, description = "User id"),
+    ),
+    responses(
+         (status = 201, description = "Member added", body = ChatMember),
+         (status = 403, description = "Not a chat admin", body = ErrorOutput),
+         (status = 404, description = "User not found", body = ErrorOutput),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn add_chat_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, uid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.is_chat_admin(id, user.id as _).await? {
+        return Err(AppError::PermissionDenied(
+            "Only a chat admin can add members".to_string(),
+        ));
+    }
+    let member = state.add_chat_member(id, uid, user.id as _).await?;
+    Ok((StatusCode::CREATED, Json(member)))
+}
+
+/// Change the role of a member, only the chat owner can change roles.
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{id}/members/{uid}",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("uid" = u64, Path, description = "User id"),
+    ),
+    request_body = UpdateMember,
+    responses(
+         (status = 200, description = "Member updated", body = ChatMember),
+         (status = 403, description = "Not the chat owner", body = ErrorOutput),
+         (status = 404, description = "Member not found", body = ErrorOutput),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn update_chat_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, uid)): Path<(u64, u64)>,
+    Json(input): Json<UpdateMember>,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.is_chat_owner(id, user.id as _).await? {
+        return Err(AppError::PermissionDenied(
+            "Only the chat owner can change roles".to_string(),
+        ));
+    }
+    let member = state.update_chat_member(id, uid, input).await?;
+    Ok(Json(member))
+}
+
+/// Remove a member from the chat, only chat admins can remove others.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/members/{uid}",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("uid" = u64, Path, description = "User id"),
+    ),
+    responses(
+         (status = 204, description = "Member removed"),
+         (status = 403, description = "Not a chat admin", body = ErrorOutput),
+         (status = 404, description = "Member not found", body = ErrorOutput),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn remove_chat_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, uid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    if uid == user.id as u64 {
+        state.leave_chat(id, uid).await?;
+        return Ok(StatusCode::NO_CONTENT);
+    }
+    if !state.is_chat_admin(id, user.id as _).await? {
+        return Err(AppError::PermissionDenied(
+            "Only a chat admin can remove members".to_string(),
+        ));
+    }
+    state.remove_chat_member(id, uid).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Create an invite code for the channel, only chat admins can invite.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/invites",
+    params(
+         ("id" = u64, Path, description = "Chat id")
+    ),
+    request_body(content = CreateInvite, description = "create invite", content_type = "application/json"),
+    responses(
+         (status = 201, description = "Invite created", body = ChatInvite),
+         (status = 400, description = "Invalid invite", body = ErrorOutput),
+         (status = 403, description = "Not a chat admin", body = ErrorOutput),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn create_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<CreateInvite>,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.is_chat_admin(id, user.id as _).await? {
+        return Err(AppError::PermissionDenied(
+            "Only a chat admin can create invites".to_string(),
+        ));
+    }
+    let invite = state.create_invite(input, id, user.id as _).await?;
+    Ok((StatusCode::CREATED, Json(invite)))
+}
+
+/// List the invites of the chat which are not revoked, only chat admins can list them.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/invites",
+    params(
+         ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+         (status = 200, description = "List of invites", body = Vec<ChatInvite>),
+         (status = 403, description = "Not a chat admin", body = ErrorOutput),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn list_invites_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.is_chat_admin(id, user.id as _).await? {
+        return Err(AppError::PermissionDenied(
+            "Only a chat admin can list invites".to_string(),
+        ));
+    }
+    let invites = state.list_invites(id).await?;
+    Ok(Json(invites))
+}
+
+/// Revoke an invite of the chat, only chat admins can revoke it.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/invites/{iid}",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("iid" = u64, Path, description = "Invite id"),
+    ),
+    responses(
+         (status = 204, description = "Invite revoked"),
+         (status = 403, description = "Not a chat admin", body = ErrorOutput),
+         (status = 404, description = "Invite not found", body = ErrorOutput),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn revoke_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, iid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.is_chat_admin(id, user.id as _).await? {
+        return Err(AppError::PermissionDenied(
+            "Only a chat admin can revoke invites".to_string(),
+        ));
+    }
+    state.revoke_invite(id, iid).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Accept an invite code, the user joins the chat of the invite.
+#[utoipa::path(
+    post,
+    path = "/api/invites/{code}/accept",
+    params(
+         ("code" = String, Path, description = "Invite code")
+    ),
+    responses(
+         (status = 200, description = "Chat joined", body = Chat),
+         (status = 400, description = "Invite revoked, expired or used up", body = ErrorOutput),
+         (status = 404, description = "Invite not found", body = ErrorOutput),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn accept_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state
+        .accept_invite(&code, user.id as _, user.ws_id as _)
+        .await?;
+    Ok(Json(chat))
+}
+
+/// Archive the chat, it keeps its history but accepts no new messages.
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/archive",
+    params(
+         ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+         (status = 200, description = "Chat archived", body = Chat),
+         (status = 403, description = "Not a chat admin", body = ErrorOutput),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn archive_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.is_chat_admin(id, user.id as _).await? {
+        return Err(AppError::PermissionDenied(
+            "Only a chat admin can archive the chat".to_string(),
+        ));
+    }
+    let chat = state.archive_chat(id).await?;
+    Ok(Json(chat))
+}
+
+/// Unarchive the chat, it accepts new messages again.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/archive",
+    params(
+         ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+         (status = 200, description = "Chat unarchived", body = Chat),
+         (status = 403, description = "Not a chat admin", body = ErrorOutput),
+    ),
+    tag="chat",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn unarchive_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.is_chat_admin(id, user.id as _).await? {
+        return Err(AppError::PermissionDenied(
+            "Only a chat admin can unarchive the chat".to_string(),
+        ));
+    }
+    let chat = state.unarchive_chat(id).await?;
+    Ok(Json(chat))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use chat_core::ChatRole;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn update_chat_member_should_need_the_owner() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = UpdateMember {
+            role: ChatRole::Admin,
+        };
+        // user 2 is a plain member of chat 2
+        let user = state.find_user_by_id(2).await?.expect("user should exist");
+        let ret = update_chat_member_handler(
+            Extension(user),
+            State(state.clone()),
+            Path((2, 3)),
+            Json(input.clone()),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let ret: ErrorOutput = serde_json::from_slice(&body)?;
+        assert_eq!(
+            ret.error,
+            "permission denied: Only the chat owner can change roles"
+        );
+
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let ret =
+            update_chat_member_handler(Extension(user), State(state), Path((2, 3)), Json(input))
+                .await
+                .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let member: ChatMember = serde_json::from_slice(&body)?;
+        assert_eq!(member.role, ChatRole::Admin);
+
+        Ok(())
+    }
+}
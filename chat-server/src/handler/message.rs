@@ -1,4 +1,8 @@
-use crate::model::{CreateMessage, ListMessages};
+use crate::model::{
+    CreateMessage, ForwardMessage, GetMessageContext, ListMentions, ListMessages, ListSaved,
+    MessageContext, MessagePage, RescheduleMessage, SaveMessage, SearchMessages, SearchOutput,
+    UpdateMessage, VotePoll,
+};
 use crate::{model::ChatFile, AppError, AppState, ErrorOutput};
 use axum::extract::Query;
 use axum::http::StatusCode;
@@ -8,20 +12,23 @@ use axum::{
     response::IntoResponse,
     Extension, Json,
 };
-use chat_core::{Message, User};
+use chat_core::{
+    Mention, Message, MessageRevision, Pin, Poll, Reaction, ReadReceipt, SavedMessage,
+    ScheduledMessage, User,
+};
 use tokio::fs;
 use tracing::{info, warn};
 
-/// List all messages in the chat.
+/// List the messages in the chat page by page, newest first.
 #[utoipa::path(
     get,
     path = "/api/chats/{id}/messages",
     params(
          ("id" = u64, Path, description = "Chat id"),
+         ListMessages,
     ),
-    request_body(content = ListMessages, description = "获取消息", content_type = "application/json"),
     responses(
-         (status = 200, description = "List of messages", body = Vec<Message>),
+         (status = 200, description = "Page of messages", body = MessagePage),
          (status = 400, description = "Invalid input", body = ErrorOutput),
     ),
     tag="message",
@@ -30,24 +37,58 @@ use tracing::{info, warn};
     )
 )]
 pub(crate) async fn list_message_handler(
+    Extension(user): Extension<User>,
     State(state): State<AppState>,
     Path(chat_id): Path<i64>,
     Query(input): Query<ListMessages>,
 ) -> Result<impl IntoResponse, AppError> {
-    let messages = state.list_message(input, chat_id as _).await?;
+    let messages = state
+        .list_message(input, chat_id as _, user.id as _)
+        .await?;
     Ok(Json(messages))
 }
 
-/// Send a new message in the chat.
+/// Resolve a message permalink to its chat and the messages around it.
+#[utoipa::path(
+    get,
+    path = "/api/messages/{mid}",
+    params(
+         ("mid" = u64, Path, description = "Message id"),
+         GetMessageContext,
+    ),
+    responses(
+         (status = 200, description = "Message with its context", body = MessageContext),
+         (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn get_message_context_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(message_id): Path<u64>,
+    Query(input): Query<GetMessageContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let context = state
+        .get_message_context(message_id, input, user.id as _)
+        .await?;
+    Ok(Json(context))
+}
+
+/// Send a new message in the chat, or schedule it when `send_at` is set.
 #[utoipa::path(
     post,
     path = "/api/chats/{id}/messages",
     params(
          ("id" = u64, Path, description = "Chat id"),
+         ("Idempotency-Key" = Option<String>, Header, description = "Same as `client_nonce` of the body"),
     ),
     request_body(content = CreateMessage, description = "创建消息", content_type = "application/json"),
     responses(
          (status = 201, description = "Message created", body = Message),
+         (status = 202, description = "Message scheduled", body = ScheduledMessage),
     ),
     tag="message",
     security(
@@ -58,12 +99,578 @@ pub(crate) async fn send_message_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
     Path(chat_id): Path<i64>,
-    Json(input): Json<CreateMessage>,
+    headers: HeaderMap,
+    Json(mut input): Json<CreateMessage>,
 ) -> Result<impl IntoResponse, AppError> {
+    if let Some(key) = headers.get("idempotency-key") {
+        let key = key.to_str().map_err(|_| {
+            AppError::CreateMessageError("Invalid Idempotency-Key header".to_string())
+        })?;
+        match &input.client_nonce {
+            Some(nonce) if nonce != key => {
+                return Err(AppError::CreateMessageError(
+                    "Idempotency-Key and client_nonce do not match".to_string(),
+                ))
+            }
+            _ => input.client_nonce = Some(key.to_string()),
+        }
+    }
+    if input.send_at.is_some() {
+        let scheduled = state
+            .schedule_message(input, chat_id as _, user.id as _)
+            .await?;
+        return Ok((StatusCode::ACCEPTED, Json(scheduled)).into_response());
+    }
     let msg = state
         .create_message(input, chat_id as _, user.id as _)
         .await?;
-    Ok((StatusCode::CREATED, Json(msg)))
+    Ok((StatusCode::CREATED, Json(msg)).into_response())
+}
+
+/// Forward a message to other chats the user is a member of.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/messages/{mid}/forward",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("mid" = u64, Path, description = "Message id"),
+    ),
+    request_body(content = ForwardMessage, description = "forward message", content_type = "application/json"),
+    responses(
+         (status = 201, description = "Forwarded messages", body = Vec<Message>),
+         (status = 403, description = "Not a member of the target chat", body = ErrorOutput),
+         (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn forward_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, message_id)): Path<(u64, u64)>,
+    Json(input): Json<ForwardMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let messages = state
+        .forward_message(input, chat_id, message_id, user.id as _)
+        .await?;
+    Ok((StatusCode::CREATED, Json(messages)))
+}
+
+/// List the pending scheduled messages of the user in the chat.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/scheduled",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+         (status = 200, description = "List of scheduled messages", body = Vec<ScheduledMessage>),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn list_scheduled_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let scheduled = state.list_scheduled(chat_id, user.id as _).await?;
+    Ok(Json(scheduled))
+}
+
+/// Change when a scheduled message is sent.
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{id}/scheduled/{sid}",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("sid" = u64, Path, description = "Scheduled message id"),
+    ),
+    request_body(content = RescheduleMessage, description = "reschedule message", content_type = "application/json"),
+    responses(
+         (status = 200, description = "Scheduled message", body = ScheduledMessage),
+         (status = 404, description = "Scheduled message not found", body = ErrorOutput),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn reschedule_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, id)): Path<(u64, u64)>,
+    Json(input): Json<RescheduleMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let scheduled = state
+        .reschedule_message(input, chat_id, id, user.id as _)
+        .await?;
+    Ok(Json(scheduled))
+}
+
+/// Cancel a scheduled message before it is sent.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/scheduled/{sid}",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("sid" = u64, Path, description = "Scheduled message id"),
+    ),
+    responses(
+         (status = 200, description = "Scheduled message canceled"),
+         (status = 404, description = "Scheduled message not found", body = ErrorOutput),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn cancel_scheduled_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state.cancel_scheduled(chat_id, id, user.id as _).await?;
+    Ok(StatusCode::OK)
+}
+
+/// Edit a message in the chat, only the sender can do it.
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{id}/messages/{mid}",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("mid" = u64, Path, description = "Message id"),
+    ),
+    request_body(content = UpdateMessage, description = "update message", content_type = "application/json"),
+    responses(
+         (status = 200, description = "Message updated", body = Message),
+         (status = 403, description = "Not the sender", body = ErrorOutput),
+         (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn update_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, message_id)): Path<(u64, u64)>,
+    Json(input): Json<UpdateMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let msg = state
+        .update_message(input, chat_id, message_id, user.id as _)
+        .await?;
+    Ok(Json(msg))
+}
+
+/// Delete a message in the chat, only the sender or a chat admin can do it.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/messages/{mid}",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("mid" = u64, Path, description = "Message id"),
+    ),
+    responses(
+         (status = 200, description = "Message deleted"),
+         (status = 403, description = "Not the sender or a chat admin", body = ErrorOutput),
+         (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn delete_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, message_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .delete_message(chat_id, message_id, user.id as _)
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+/// List the replies in the thread of a message.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/messages/{mid}/thread",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("mid" = u64, Path, description = "Thread root message id"),
+         ListMessages,
+    ),
+    responses(
+         (status = 200, description = "Page of replies", body = MessagePage),
+         (status = 404, description = "Thread not found", body = ErrorOutput),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn list_thread_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, message_id)): Path<(u64, u64)>,
+    Query(input): Query<ListMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let messages = state
+        .list_thread(input, chat_id, message_id, user.id as _)
+        .await?;
+    Ok(Json(messages))
+}
+
+/// React to a message with an emoji.
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/messages/{mid}/reactions/{emoji}",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("mid" = u64, Path, description = "Message id"),
+         ("emoji" = String, Path, description = "Emoji"),
+    ),
+    responses(
+         (status = 200, description = "Reactions of the message", body = Vec<Reaction>),
+         (status = 400, description = "Invalid emoji", body = ErrorOutput),
+         (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn add_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, message_id, emoji)): Path<(u64, u64, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let reactions = state
+        .add_reaction(chat_id, message_id, user.id as _, &emoji)
+        .await?;
+    Ok(Json(reactions))
+}
+
+/// Remove an emoji reaction from a message.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/messages/{mid}/reactions/{emoji}",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("mid" = u64, Path, description = "Message id"),
+         ("emoji" = String, Path, description = "Emoji"),
+    ),
+    responses(
+         (status = 200, description = "Reactions of the message", body = Vec<Reaction>),
+         (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn remove_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, message_id, emoji)): Path<(u64, u64, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let reactions = state
+        .remove_reaction(chat_id, message_id, user.id as _, &emoji)
+        .await?;
+    Ok(Json(reactions))
+}
+
+/// Pin a message in the chat.
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/messages/{mid}/pin",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("mid" = u64, Path, description = "Message id"),
+    ),
+    responses(
+         (status = 200, description = "Pinned message", body = Pin),
+         (status = 400, description = "Too many pinned messages", body = ErrorOutput),
+         (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn pin_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, message_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let pin = state.pin_message(chat_id, message_id, user.id as _).await?;
+    Ok(Json(pin))
+}
+
+/// Unpin a message in the chat.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/messages/{mid}/pin",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("mid" = u64, Path, description = "Message id"),
+    ),
+    responses(
+         (status = 200, description = "Message unpinned"),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn unpin_message_handler(
+    State(state): State<AppState>,
+    Path((chat_id, message_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state.unpin_message(chat_id, message_id).await?;
+    Ok(StatusCode::OK)
+}
+
+/// Vote on a poll, the votes of the user replace the previous ones.
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/messages/{mid}/votes",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("mid" = u64, Path, description = "Message id of the poll"),
+    ),
+    request_body(content = VotePoll, description = "vote poll", content_type = "application/json"),
+    responses(
+         (status = 200, description = "Poll with the tallies", body = Poll),
+         (status = 400, description = "Invalid vote or poll closed", body = ErrorOutput),
+         (status = 404, description = "Poll not found", body = ErrorOutput),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn vote_poll_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, message_id)): Path<(u64, u64)>,
+    Json(input): Json<VotePoll>,
+) -> Result<impl IntoResponse, AppError> {
+    let poll = state
+        .vote_poll(input, chat_id, message_id, user.id as _)
+        .await?;
+    Ok(Json(poll))
+}
+
+/// List the pinned messages of the chat, latest pinned first.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/pins",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+         (status = 200, description = "Pinned messages", body = Vec<Pin>),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn list_pins_handler(
+    State(state): State<AppState>,
+    Path(chat_id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let pins = state.list_pins(chat_id).await?;
+    Ok(Json(pins))
+}
+
+/// Save a message for later with an optional note and reminder.
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/messages/{mid}/save",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("mid" = u64, Path, description = "Message id"),
+    ),
+    request_body(content = SaveMessage, description = "save message", content_type = "application/json"),
+    responses(
+         (status = 200, description = "Saved message", body = SavedMessage),
+         (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn save_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, message_id)): Path<(u64, u64)>,
+    Json(input): Json<SaveMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let saved = state
+        .save_message(input, chat_id, message_id, user.id as _)
+        .await?;
+    Ok(Json(saved))
+}
+
+/// Remove a message from the saved messages.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/messages/{mid}/save",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("mid" = u64, Path, description = "Message id"),
+    ),
+    responses(
+         (status = 200, description = "Message unsaved"),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn unsave_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, message_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .unsave_message(chat_id, message_id, user.id as _)
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+/// List the saved messages of the user, latest saved first.
+#[utoipa::path(
+    get,
+    path = "/api/saved",
+    params(
+         ListSaved,
+    ),
+    responses(
+         (status = 200, description = "List of saved messages", body = Vec<SavedMessage>),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn list_saved_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListSaved>,
+) -> Result<impl IntoResponse, AppError> {
+    let saved = state.list_saved(input, user.id as _).await?;
+    Ok(Json(saved))
+}
+
+/// List the members who have read the message.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/messages/{mid}/reads",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("mid" = u64, Path, description = "Message id"),
+    ),
+    responses(
+         (status = 200, description = "List of read receipts", body = Vec<ReadReceipt>),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn list_message_reads_handler(
+    State(state): State<AppState>,
+    Path((chat_id, message_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let receipts = state.list_message_reads(chat_id, message_id).await?;
+    Ok(Json(receipts))
+}
+
+/// List the revision history of a message.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/messages/{mid}/revisions",
+    params(
+         ("id" = u64, Path, description = "Chat id"),
+         ("mid" = u64, Path, description = "Message id"),
+    ),
+    responses(
+         (status = 200, description = "List of revisions", body = Vec<MessageRevision>),
+         (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn list_message_revisions_handler(
+    State(state): State<AppState>,
+    Path((chat_id, message_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let revisions = state.list_message_revisions(chat_id, message_id).await?;
+    Ok(Json(revisions))
+}
+
+/// List the mentions of the user, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/mentions",
+    params(
+         ListMentions,
+    ),
+    responses(
+         (status = 200, description = "List of mentions", body = Vec<Mention>),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn list_mentions_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListMentions>,
+) -> Result<impl IntoResponse, AppError> {
+    let mentions = state.list_mentions(input, user.id as _).await?;
+    Ok(Json(mentions))
+}
+
+/// Search messages in the chats of the user, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/search",
+    params(
+         SearchMessages,
+    ),
+    responses(
+         (status = 200, description = "Matched messages", body = SearchOutput),
+         (status = 400, description = "Invalid search query", body = ErrorOutput),
+    ),
+    tag="message",
+    security(
+         ("token" = [])
+    )
+)]
+pub(crate) async fn search_messages_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<SearchMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let output = state
+        .search_messages(input, user.id as _, user.ws_id as _)
+        .await?;
+    Ok(Json(output))
 }
 
 #[utoipa::path(
@@ -10,14 +10,19 @@ use crate::openapi::OpenApiRouter;
 use anyhow::Context;
 use axum::http::Method;
 use axum::middleware::from_fn_with_state;
-use axum::routing::{get, post};
+use axum::routing::{delete, get, patch, post, put};
 use axum::Router;
 use chat_core::{set_layer, verify_token, DecodingKey, EncodingKey, TokenVerify, User};
 pub use config::AppConfig;
 pub use error::AppError;
 pub use error::ErrorOutput;
 use handler::*;
-pub use model::{CreateChat, CreateMessage, CreateUser, ListMessages, SigninUser};
+pub use model::{
+    ChannelInfo, CreateChat, CreateInvite, CreateMessage, CreatePoll, CreateUser, ForwardMessage,
+    GetMessageContext, ListChannels, ListMembers, ListMentions, ListMessages, ListSaved, MarkRead,
+    MessageContext, MessagePage, RescheduleMessage, SaveDraft, SaveMessage, SearchHit,
+    SearchMessages, SearchOutput, SigninUser, UpdateMember, UpdateMessage, VotePoll,
+};
 use sqlx::PgPool;
 use std::fmt;
 use std::ops::Deref;
@@ -80,6 +85,10 @@ impl fmt::Debug for AppStateInner {
 }
 
 pub async fn get_router(state: AppState) -> Result<Router, AppError> {
+    model::setup_scheduled_dispatcher(state.clone());
+    model::setup_expiry_sweeper(state.clone());
+    model::setup_reminder_sweeper(state.clone());
+
     let chat = Router::new()
         .route(
             "/{id}",
@@ -91,8 +100,70 @@ pub async fn get_router(state: AppState) -> Result<Router, AppError> {
             "/{id}/messages",
             get(list_message_handler).post(send_message_handler),
         )
+        .route(
+            "/{id}/messages/{mid}",
+            patch(update_message_handler).delete(delete_message_handler),
+        )
+        .route("/{id}/read", post(mark_read_handler))
+        .route("/{id}/members", get(list_chat_members_handler))
+        .route(
+            "/{id}/members/{uid}",
+            post(add_chat_member_handler)
+                .patch(update_chat_member_handler)
+                .delete(remove_chat_member_handler),
+        )
+        .route("/{id}/leave", post(leave_chat_handler))
+        .route(
+            "/{id}/archive",
+            put(archive_chat_handler).delete(unarchive_chat_handler),
+        )
+        .route(
+            "/{id}/invites",
+            get(list_invites_handler).post(create_invite_handler),
+        )
+        .route("/{id}/invites/{iid}", delete(revoke_invite_handler))
+        .route(
+            "/{id}/draft",
+            get(get_draft_handler)
+                .put(save_draft_handler)
+                .delete(delete_draft_handler),
+        )
+        .route(
+            "/{id}/messages/{mid}/forward",
+            post(forward_message_handler),
+        )
+        .route("/{id}/messages/{mid}/thread", get(list_thread_handler))
+        .route(
+            "/{id}/messages/{mid}/reads",
+            get(list_message_reads_handler),
+        )
+        .route(
+            "/{id}/messages/{mid}/reactions/{emoji}",
+            put(add_reaction_handler).delete(remove_reaction_handler),
+        )
+        .route(
+            "/{id}/messages/{mid}/revisions",
+            get(list_message_revisions_handler),
+        )
+        .route(
+            "/{id}/messages/{mid}/pin",
+            put(pin_message_handler).delete(unpin_message_handler),
+        )
+        .route("/{id}/pins", get(list_pins_handler))
+        .route("/{id}/messages/{mid}/votes", put(vote_poll_handler))
+        .route("/{id}/scheduled", get(list_scheduled_handler))
+        .route(
+            "/{id}/scheduled/{sid}",
+            patch(reschedule_message_handler).delete(cancel_scheduled_handler),
+        )
+        .route(
+            "/{id}/messages/{mid}/save",
+            put(save_message_handler).delete(unsave_message_handler),
+        )
         .layer(from_fn_with_state(state.clone(), verify_chat))
-        .route("/", get(list_chat_handler).post(create_chat_handler));
+        .route("/", get(list_chat_handler).post(create_chat_handler))
+        .route("/archived", get(list_archived_chats_handler))
+        .route("/{id}/join", post(join_chat_handler));
 
     let cors = CorsLayer::new()
         .allow_methods([
@@ -107,6 +178,12 @@ pub async fn get_router(state: AppState) -> Result<Router, AppError> {
     let api = Router::new()
         .route("/users", get(list_chat_users_handler))
         .nest("/chats", chat)
+        .route("/channels", get(list_channels_handler))
+        .route("/invites/{code}/accept", post(accept_invite_handler))
+        .route("/mentions", get(list_mentions_handler))
+        .route("/messages/{mid}", get(get_message_context_handler))
+        .route("/search", get(search_messages_handler))
+        .route("/saved", get(list_saved_handler))
         .route("/upload", post(upload_handler))
         .route("/files/{ws_id}/{*path}", get(file_handler))
         .route_layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
@@ -1,5 +1,6 @@
 use crate::{AppError, AppState};
 use axum::extract::Path;
+use axum::http::Method;
 use axum::{
     extract::Request,
     extract::State,
@@ -8,19 +9,32 @@ use axum::{
     Extension,
 };
 use chat_core::User;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct ChatPath {
+    id: u64,
+}
 
 pub async fn verify_chat(
     State(state): State<AppState>,
-    Path(chat_id): Path<u64>,
+    Path(ChatPath { id: chat_id }): Path<ChatPath>,
     user: Extension<User>,
     req: Request,
     next: Next,
 ) -> Response {
-    if !state
+    let is_member = state
         .is_chat_member(chat_id, user.id as _)
         .await
-        .unwrap_or_default()
-    {
+        .unwrap_or_default();
+    // non-members can read public channels of their workspace
+    let can_preview = !is_member
+        && req.method() == Method::GET
+        && state
+            .can_preview_chat(chat_id, user.id as _)
+            .await
+            .unwrap_or_default();
+    if !is_member && !can_preview {
         let err = AppError::CreateMessageError(format!(
             "User {} are not as member of chat {chat_id}",
             user.id
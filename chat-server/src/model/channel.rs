@@ -0,0 +1,230 @@
+use crate::{AppError, AppState};
+use chat_core::{Chat, ChatType};
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct ListChannels {
+    /// part of the channel name, case insensitive
+    #[serde(default)]
+    pub q: String,
+    #[serde(default)]
+    pub last_id: Option<u64>,
+    #[serde(default)]
+    pub limit: u64,
+}
+
+/// A public channel as listed in the workspace directory.
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ChannelInfo {
+    pub id: i64,
+    pub name: String,
+    pub member_count: i64,
+    /// whether the user is a member of the channel
+    pub joined: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AppState {
+    /// List the public channels of the workspace, with their member counts.
+    pub async fn list_channels(
+        &self,
+        input: ListChannels,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Vec<ChannelInfo>, AppError> {
+        let last_id = input.last_id.unwrap_or(0);
+        let limit = match input.limit {
+            0 => 20,
+            1..=100 => input.limit as _,
+            _ => 100,
+        };
+        let channels = sqlx::query_as(
+            r#"
+            SELECT c.id, c.name, c.created_at,
+                (SELECT COUNT(*) FROM chat_members cm WHERE cm.chat_id = c.id) AS member_count,
+                EXISTS (
+                    SELECT 1 FROM chat_members cm WHERE cm.chat_id = c.id AND cm.user_id = $2
+                ) AS joined
+            FROM chats c
+            WHERE c.ws_id = $1 AND c.type = 'public_channel' AND c.id > $3
+                AND ($4 = '' OR strpos(lower(c.name), lower($4)) > 0)
+            ORDER BY c.id
+            LIMIT $5
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(last_id as i64)
+        .bind(input.q.trim())
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(channels)
+    }
+
+    /// Whether the user can read the chat without being a member,
+    /// which holds for the public channels of the user's workspace.
+    pub async fn can_preview_chat(&self, chat_id: u64, user_id: u64) -> Result<bool, AppError> {
+        let can_preview = sqlx::query(
+            r#"
+            SELECT 1
+            FROM chats c
+            JOIN users u ON u.ws_id = c.ws_id
+            WHERE c.id = $1 AND u.id = $2 AND c.type = 'public_channel'
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(can_preview.is_some())
+    }
+
+    /// Join a public channel of the user's workspace, joining it again is a no-op.
+    pub async fn join_chat(&self, chat_id: u64, user_id: u64) -> Result<Chat, AppError> {
+        let chat = self
+            .get_chat_by_id(chat_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))?;
+        if !self.can_preview_chat(chat_id, user_id).await? {
+            return Err(AppError::PermissionDenied(
+                "Only public channels of the workspace can be joined".to_string(),
+            ));
+        }
+        if chat.members.contains(&(user_id as i64)) {
+            return Ok(chat);
+        }
+        if chat.archived_at.is_some() {
+            return Err(AppError::UpdateChatError(
+                "Cannot change an archived chat".to_string(),
+            ));
+        }
+
+        let mut conn = self.pool.acquire().await?;
+        self.insert_chat_member(&mut conn, chat_id, user_id, user_id)
+            .await?;
+
+        self.get_chat_by_id(chat_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))
+    }
+
+    /// Leave the chat, when its last owner leaves the longest standing member takes over.
+    pub async fn leave_chat(&self, chat_id: u64, user_id: u64) -> Result<(), AppError> {
+        let chat = self
+            .get_chat_by_id(chat_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))?;
+        if chat.r#type == ChatType::Single {
+            return Err(AppError::UpdateChatError(
+                "Cannot leave a single chat".to_string(),
+            ));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        // lock the members so that two owners leaving at once still leave one behind
+        sqlx::query("SELECT 1 FROM chat_members WHERE chat_id = $1 FOR UPDATE")
+            .bind(chat_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM chat_members WHERE chat_id = $1 AND user_id = $2")
+            .bind(chat_id as i64)
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            UPDATE chat_members
+            SET role = 'owner'
+            WHERE chat_id = $1
+                AND user_id = (
+                    SELECT user_id
+                    FROM chat_members
+                    WHERE chat_id = $1
+                    ORDER BY role, joined_at, user_id
+                    LIMIT 1
+                )
+                AND NOT EXISTS (SELECT 1 FROM chat_members WHERE chat_id = $1 AND role = 'owner')
+            "#,
+        )
+        .bind(chat_id as i64)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateChat;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn list_channels_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateChat::new("General Talk", &[2, 3], true);
+        state.create_chat(input, 2, 1).await?;
+
+        let channels = state.list_channels(ListChannels::default(), 1, 1).await?;
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[0].name, "general");
+        assert_eq!(channels[0].member_count, 5);
+        assert!(channels[0].joined);
+        assert_eq!(channels[1].member_count, 2);
+        assert!(!channels[1].joined);
+
+        let input = ListChannels {
+            q: "TALK".to_string(),
+            ..Default::default()
+        };
+        let channels = state.list_channels(input, 1, 1).await?;
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].name, "General Talk");
+
+        // private channels are not listed
+        let input = ListChannels {
+            q: "private".to_string(),
+            ..Default::default()
+        };
+        assert!(state.list_channels(input, 1, 1).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn join_and_leave_chat_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateChat::new("lobby", &[2, 3], true);
+        let chat = state.create_chat(input, 2, 1).await?;
+        let id = chat.id as u64;
+
+        assert!(!state.is_chat_member(id, 1).await?);
+        assert!(state.can_preview_chat(id, 1).await?);
+        let chat = state.join_chat(id, 1).await?;
+        assert_eq!(chat.members, vec![2, 3, 1]);
+        let chat = state.join_chat(id, 1).await?;
+        assert_eq!(chat.members.len(), 3);
+
+        // private channels can not be joined
+        let err = state.join_chat(2, 4).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        let err = state.leave_chat(3, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateChatError(_)));
+
+        // the owner leaves, the longest standing member takes over
+        state.leave_chat(id, 2).await?;
+        assert!(!state.is_chat_member(id, 2).await?);
+        assert!(state.is_chat_admin(id, 3).await?);
+        assert!(!state.is_chat_admin(id, 1).await?);
+
+        Ok(())
+    }
+}
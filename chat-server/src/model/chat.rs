@@ -3,6 +3,8 @@ use chat_core::{Chat, ChatType};
 use serde::{Deserialize, Serialize};
 use utoipa::{IntoParams, ToSchema};
 
+const MAX_MESSAGE_TTL: u32 = 365 * 24 * 3600;
+
 #[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
 pub struct CreateChat {
     pub name: Option<String>,
@@ -15,10 +17,14 @@ pub struct UpdateChat {
     pub name: Option<String>,
     pub members: Option<Vec<i64>>,
     pub chat_type: Option<ChatType>,
+    /// seconds before new messages disappear, 0 turns disappearing messages off
+    pub message_ttl: Option<u32>,
 }
 
 #[allow(dead_code)]
 impl AppState {
+    /// Create a chat owned by the user, a named chat with the same name and members as an existing
+    /// one of the workspace is rejected, as the unique constraint on the dropped chats.members did.
     pub async fn create_chat(
         &self,
         input: CreateChat,
@@ -63,32 +69,111 @@ impl AppState {
             }
         };
 
-        let chat = sqlx::query_as(
+        let mut tx = self.pool.begin().await?;
+        // serialize the chat creations of the workspace so the same chat is not created twice
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))")
+            .bind(format!("chats:{ws_id}"))
+            .execute(&mut *tx)
+            .await?;
+        let existing = sqlx::query(
+            r#"
+            SELECT 1
+            FROM chats c
+            WHERE c.ws_id = $1 AND c.name = $2
+                AND ARRAY(SELECT user_id FROM chat_members WHERE chat_id = c.id ORDER BY user_id)
+                    = ARRAY(SELECT DISTINCT m FROM unnest($3::bigint[]) AS m ORDER BY m)
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(&input.name)
+        .bind(&input.members)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if existing.is_some() {
+            return Err(AppError::CreateChatError(
+                "A chat with the same name and members already exists".to_string(),
+            ));
+        }
+        let (id,): (i64,) = sqlx::query_as(
             r#"
-            INSERT INTO chats (ws_id, name, type, members)
-            VALUES ($1, $2, $3, $4)
-            RETURNING id, ws_id, name, type, members,created_at
+            INSERT INTO chats (ws_id, name, type)
+            VALUES ($1, $2, $3)
+            RETURNING id
                 "#,
         )
         .bind(ws_id as i64)
         .bind(input.name)
         .bind(chat_type)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // the creator owns the chat
+        sqlx::query(
+            r#"
+            INSERT INTO chat_members (chat_id, user_id, role, added_by)
+            SELECT $1, m, CASE WHEN m = $3 THEN 'owner'::chat_role ELSE 'member'::chat_role END, $3
+            FROM unnest($2::bigint[]) AS m
+                "#,
+        )
+        .bind(id)
         .bind(&input.members)
-        .fetch_one(&self.pool)
+        .bind(user_id as i64)
+        .execute(&mut *tx)
         .await?;
-        Ok(chat)
+        tx.commit().await?;
+
+        self.get_chat_by_id(id as u64)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {}", id)))
     }
 
+    /// List the chats of the user which are not archived.
     pub async fn fetch_chats(&self, user_id: u64, ws_id: u64) -> Result<Vec<Chat>, AppError> {
+        self.fetch_chats_by_state(user_id, ws_id, false).await
+    }
+
+    pub async fn fetch_archived_chats(
+        &self,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Vec<Chat>, AppError> {
+        self.fetch_chats_by_state(user_id, ws_id, true).await
+    }
+
+    async fn fetch_chats_by_state(
+        &self,
+        user_id: u64,
+        ws_id: u64,
+        archived: bool,
+    ) -> Result<Vec<Chat>, AppError> {
         let chats = sqlx::query_as(
             r#"
-            SELECT id, ws_id, name, type, members, created_at
-            FROM chats
-            WHERE ws_id = $1 AND $2 = ANY(members)
+            SELECT c.id, c.ws_id, c.name, c.type, chat_member_ids(c.id) AS members, c.created_at,
+                c.message_ttl, c.archived_at,
+                r.last_read_message_id AS last_read_id,
+                (
+                    SELECT COUNT(*)
+                    FROM messages m
+                    WHERE m.chat_id = c.id
+                        AND m.id > COALESCE(r.last_read_message_id, 0)
+                        AND m.sender_id <> $2
+                        AND m.parent_id IS NULL
+                        AND m.deleted_at IS NULL
+                ) AS unread_count,
+                (
+                    SELECT to_jsonb(d)
+                    FROM chat_drafts d
+                    WHERE d.chat_id = c.id AND d.user_id = $2
+                ) AS draft
+            FROM chats c
+            JOIN chat_members cm ON cm.chat_id = c.id AND cm.user_id = $2
+            LEFT JOIN chat_reads r ON r.chat_id = c.id AND r.user_id = $2
+            WHERE c.ws_id = $1 AND (c.archived_at IS NOT NULL) = $3
                 "#,
         )
         .bind(ws_id as i64)
         .bind(user_id as i64)
+        .bind(archived)
         .fetch_all(&self.pool)
         .await?;
 
@@ -98,7 +183,9 @@ impl AppState {
     pub async fn get_chat_by_id(&self, id: u64) -> Result<Option<Chat>, AppError> {
         let chat = sqlx::query_as(
             r#"
-            SELECT id, ws_id, name, type, members, created_at
+            SELECT id, ws_id, name, type, chat_member_ids(id) AS members, created_at, message_ttl,
+                archived_at,
+                (SELECT COUNT(*) FROM message_pins WHERE chat_id = chats.id) AS pin_count
             FROM chats
             WHERE id = $1
                 "#,
@@ -110,19 +197,30 @@ impl AppState {
         Ok(chat)
     }
 
-    pub async fn update_chat_by_id(&self, id: u64, input: UpdateChat) -> Result<Chat, AppError> {
+    /// Update the chat, the new members are added by the user and the owner can not be removed.
+    pub async fn update_chat_by_id(
+        &self,
+        id: u64,
+        input: UpdateChat,
+        user_id: u64,
+    ) -> Result<Chat, AppError> {
         let chat = self.get_chat_by_id(id).await?;
         let chat = match chat {
             None => return Err(AppError::NotFound(format!("chat id {}", id))),
             Some(chat) => chat,
         };
+        if chat.archived_at.is_some() {
+            return Err(AppError::UpdateChatError(
+                "Cannot change an archived chat".to_string(),
+            ));
+        }
 
         let mut name = chat.name;
         if let Some(new_name) = input.name {
             name = Some(new_name);
         }
 
-        let mut members = chat.members;
+        let mut members = None;
         if let Some(new_members) = input.members {
             if chat.r#type.eq(&ChatType::Single) {
                 return Err(AppError::UpdateChatError(
@@ -135,7 +233,18 @@ impl AppState {
                     "Some members do not exist".to_string(),
                 ));
             }
-            members = new_members;
+            let owners: Vec<i64> = sqlx::query_scalar(
+                "SELECT user_id FROM chat_members WHERE chat_id = $1 AND role = 'owner'",
+            )
+            .bind(id as i64)
+            .fetch_all(&self.pool)
+            .await?;
+            if owners.iter().any(|owner| !new_members.contains(owner)) {
+                return Err(AppError::PermissionDenied(
+                    "The chat owner can not be removed".to_string(),
+                ));
+            }
+            members = Some(new_members);
         }
 
         let mut r#type = chat.r#type;
@@ -148,22 +257,127 @@ impl AppState {
             r#type = new_type;
         }
 
-        let chat = sqlx::query_as(
+        let mut message_ttl = chat.message_ttl;
+        if let Some(ttl) = input.message_ttl {
+            if ttl > MAX_MESSAGE_TTL {
+                return Err(AppError::UpdateChatError(format!(
+                    "Message ttl must be at most {MAX_MESSAGE_TTL} seconds"
+                )));
+            }
+            message_ttl = (ttl > 0).then_some(ttl as i32);
+        }
+
+        let mut tx = self.pool.begin().await?;
+        if let Some(members) = members {
+            sqlx::query(
+                r#"
+                DELETE FROM chat_members
+                WHERE chat_id = $1 AND user_id <> ALL($2) AND role <> 'owner'
+                    "#,
+            )
+            .bind(id as i64)
+            .bind(&members)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query(
+                r#"
+                INSERT INTO chat_members (chat_id, user_id, added_by)
+                SELECT $1, m, $3
+                FROM unnest($2::bigint[]) AS m
+                ON CONFLICT DO NOTHING
+                    "#,
+            )
+            .bind(id as i64)
+            .bind(&members)
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        // members are notified by their own changes, skip the no-op update
+        sqlx::query(
             r#"
             UPDATE chats
-            SET name = $1, members = $2, type = $3
-            WHERE id = $4
-            RETURNING id, ws_id, name, type, members, created_at
+            SET name = $1, type = $2, message_ttl = $4
+            WHERE id = $3 AND (name, type, message_ttl) IS DISTINCT FROM ($1, $2, $4)
                 "#,
         )
         .bind(name)
-        .bind(&members)
         .bind(r#type)
         .bind(id as i64)
-        .fetch_one(&self.pool)
+        .bind(message_ttl)
+        .execute(&mut *tx)
         .await?;
+        tx.commit().await?;
 
-        Ok(chat)
+        self.get_chat_by_id(id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {}", id)))
+    }
+
+    /// Archive the chat, its history stays readable and searchable.
+    pub async fn archive_chat(&self, id: u64) -> Result<Chat, AppError> {
+        self.set_chat_archived(id, true).await
+    }
+
+    pub async fn unarchive_chat(&self, id: u64) -> Result<Chat, AppError> {
+        self.set_chat_archived(id, false).await
+    }
+
+    /// Archiving an archived chat again keeps the time it was archived.
+    /// The pending scheduled messages of an archived chat are marked as failed.
+    async fn set_chat_archived(&self, id: u64, archived: bool) -> Result<Chat, AppError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+            UPDATE chats
+            SET archived_at = CASE WHEN $2 THEN NOW() END
+            WHERE id = $1 AND (archived_at IS NOT NULL) <> $2
+                "#,
+        )
+        .bind(id as i64)
+        .bind(archived)
+        .execute(&mut *tx)
+        .await?;
+        if archived {
+            sqlx::query(
+                r#"
+                UPDATE scheduled_messages
+                SET failed_at = NOW(), error = 'Cannot send messages to an archived chat'
+                WHERE chat_id = $1 AND failed_at IS NULL
+                "#,
+            )
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        self.get_chat_by_id(id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {}", id)))
+    }
+
+    /// Archived chats are read only, the change is rejected with the given error.
+    pub(crate) async fn verify_chat_writable(
+        &self,
+        id: u64,
+        err: fn(String) -> AppError,
+    ) -> Result<(), AppError> {
+        if self.is_chat_archived(id).await? {
+            return Err(err("Cannot change an archived chat".to_string()));
+        }
+        Ok(())
+    }
+
+    pub async fn is_chat_archived(&self, id: u64) -> Result<bool, AppError> {
+        let archived: Option<bool> =
+            sqlx::query_scalar("SELECT archived_at IS NOT NULL FROM chats WHERE id = $1")
+                .bind(id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(archived.unwrap_or_default())
     }
 
     pub async fn delete_chat_by_id(&self, id: u64) -> Result<(), AppError> {
@@ -184,8 +398,8 @@ impl AppState {
         let is_member = sqlx::query(
             r#"
             SELECT 1
-            FROM chats
-            WHERE id = $1 AND $2 = ANY(members)
+            FROM chat_members
+            WHERE chat_id = $1 AND user_id = $2
                 "#,
         )
         .bind(chat_id as i64)
@@ -195,6 +409,57 @@ impl AppState {
 
         Ok(is_member.is_some())
     }
+
+    /// Whether the user owns the chat, the workspace owner owns every chat of the workspace.
+    pub async fn is_chat_owner(&self, chat_id: u64, user_id: u64) -> Result<bool, AppError> {
+        let is_owner = sqlx::query(
+            r#"
+            SELECT 1
+            FROM chats c
+            JOIN workspaces w ON w.id = c.ws_id
+            WHERE c.id = $1 AND (
+                w.owner_id = $2
+                OR EXISTS (
+                    SELECT 1
+                    FROM chat_members cm
+                    WHERE cm.chat_id = c.id AND cm.user_id = $2 AND cm.role = 'owner'
+                )
+            )
+                "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(is_owner.is_some())
+    }
+
+    /// The owners and admins of the chat administer it, so does the owner of its workspace.
+    /// The creator of a chat is its owner.
+    pub async fn is_chat_admin(&self, chat_id: u64, user_id: u64) -> Result<bool, AppError> {
+        let is_admin = sqlx::query(
+            r#"
+            SELECT 1
+            FROM chats c
+            JOIN workspaces w ON w.id = c.ws_id
+            WHERE c.id = $1 AND (
+                w.owner_id = $2
+                OR EXISTS (
+                    SELECT 1
+                    FROM chat_members cm
+                    WHERE cm.chat_id = c.id AND cm.user_id = $2 AND cm.role IN ('owner', 'admin')
+                )
+            )
+                "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(is_admin.is_some())
+    }
 }
 
 #[cfg(test)]
@@ -216,8 +481,13 @@ impl CreateChat {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::model::{
+        CreateInvite, CreateMessage, CreatePoll, SaveDraft, UpdateMessage, VotePoll,
+    };
     use anyhow::Result;
     use chat_core::ChatType;
+    use sqlx::types::chrono::Utc;
+    use std::time::Duration;
 
     #[tokio::test]
     async fn create_single_chat_should_work() -> Result<()> {
@@ -246,6 +516,10 @@ mod tests {
         assert_eq!(chat.members.len(), 3);
         assert_eq!(chat.r#type, ChatType::PublicChannel);
 
+        let input = CreateChat::new("test", &[3, 2, 1], false);
+        let err = state.create_chat(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::CreateChatError(_)));
+
         Ok(())
     }
 
@@ -301,15 +575,40 @@ mod tests {
             name: Some("new name".to_string()),
             members: Some(vec![1, 2, 3]),
             chat_type: None,
+            message_ttl: None,
         };
         let chat = state
-            .update_chat_by_id(1, input)
+            .update_chat_by_id(1, input, 2)
             .await
             .expect("update chat failed");
         assert_eq!(chat.name.expect("chat name"), "new name");
         assert_eq!(chat.members.len(), 3);
         assert_eq!(chat.r#type, ChatType::PublicChannel);
 
+        let input = UpdateChat {
+            name: None,
+            members: Some(vec![1, 2, 4]),
+            chat_type: None,
+            message_ttl: None,
+        };
+        state.update_chat_by_id(1, input, 2).await?;
+        let member = state
+            .get_chat_member(1, 4)
+            .await?
+            .expect("member should exist");
+        assert_eq!(member.added_by, Some(2));
+
+        // the owner stays
+        let input = UpdateChat {
+            name: None,
+            members: Some(vec![2, 4]),
+            chat_type: None,
+            message_ttl: None,
+        };
+        let err = state.update_chat_by_id(1, input, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        assert!(state.is_chat_member(1, 1).await?);
+
         Ok(())
     }
 
@@ -333,4 +632,109 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn archive_chat_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let chat = state.archive_chat(1).await?;
+        let archived_at = chat.archived_at.expect("chat should be archived");
+        // archiving again keeps the time it was archived
+        let chat = state.archive_chat(1).await?;
+        assert_eq!(chat.archived_at, Some(archived_at));
+        assert!(state.is_chat_archived(1).await?);
+
+        let chats = state.fetch_chats(1, 1).await?;
+        assert_eq!(chats.len(), 3);
+        assert!(chats.iter().all(|c| c.id != 1));
+        let chats = state.fetch_archived_chats(1, 1).await?;
+        assert_eq!(chats.len(), 1);
+        assert_eq!(chats[0].id, 1);
+
+        let chat = state.unarchive_chat(1).await?;
+        assert!(chat.archived_at.is_none());
+        assert_eq!(state.fetch_chats(1, 1).await?.len(), 4);
+        assert!(state.fetch_archived_chats(1, 1).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn archived_chat_should_be_read_only() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            poll: Some(CreatePoll {
+                question: "Lunch?".to_string(),
+                options: vec!["noodles".to_string(), "rice".to_string()],
+                multiple: false,
+                anonymous: false,
+                closes_at: None,
+            }),
+            ..Default::default()
+        };
+        let message = state.create_message(input, 2, 1).await?;
+        let id = message.id as u64;
+        let invite = state.create_invite(CreateInvite::default(), 2, 1).await?;
+        let input = CreateMessage {
+            content: "later".to_string(),
+            send_at: Some(Utc::now() + Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        state.schedule_message(input, 2, 1).await?;
+        state.archive_chat(2).await?;
+
+        let input = UpdateMessage {
+            content: Some("edited".to_string()),
+            files: None,
+            format: None,
+        };
+        let err = state.update_message(input, 2, id, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateMessageError(_)));
+        let err = state.delete_message(2, id, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateMessageError(_)));
+        let err = state.add_reaction(2, id, 1, "👍").await.unwrap_err();
+        assert!(matches!(err, AppError::ReactionError(_)));
+        let err = state.pin_message(2, id, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::PinError(_)));
+        let input = VotePoll { options: vec![0] };
+        let err = state.vote_poll(input, 2, id, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::PollError(_)));
+        let input = SaveDraft {
+            content: "draft".to_string(),
+            files: vec![],
+        };
+        let err = state.save_draft(input, 2, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::DraftError(_)));
+        let err = state.add_chat_member(2, 4, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateChatError(_)));
+        let err = state.accept_invite(&invite.code, 4, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::InviteError(_)));
+        assert!(!state.is_chat_member(2, 4).await?);
+
+        // the pending scheduled messages are marked as failed
+        let scheduled = state.list_scheduled(2, 1).await?;
+        assert!(scheduled[0].failed_at.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn is_chat_admin_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // user 1 owns chat 1, user 2 is a plain member
+        assert!(state.is_chat_admin(1, 1).await?);
+        assert!(!state.is_chat_admin(1, 2).await?);
+
+        // the creator owns the new chat
+        let input = CreateChat::new("team", &[1, 2, 3], false);
+        let chat = state.create_chat(input, 2, 1).await?;
+        assert!(state.is_chat_admin(chat.id as _, 2).await?);
+        assert!(!state.is_chat_admin(chat.id as _, 3).await?);
+
+        // the workspace owner administers every chat of the workspace
+        state.update_workspace_owner(1, 3).await?;
+        assert!(state.is_chat_admin(1, 3).await?);
+        assert!(state.is_chat_admin(chat.id as _, 3).await?);
+
+        Ok(())
+    }
 }
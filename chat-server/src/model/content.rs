@@ -0,0 +1,197 @@
+use crate::AppError;
+use ammonia::{Builder, UrlRelative};
+use chat_core::ContentFormat;
+use pulldown_cmark::{html, Event, Options, Parser, Tag, TagEnd};
+use std::collections::{HashMap, HashSet};
+use std::sync::LazyLock;
+
+const LINK_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+/// Only the subset of markdown the clients are expected to render survives, other tags
+/// are dropped while their text is kept.
+static SANITIZER: LazyLock<Builder<'static>> = LazyLock::new(|| {
+    let mut builder = Builder::default();
+    builder
+        .tags(HashSet::from([
+            "p",
+            "br",
+            "em",
+            "strong",
+            "del",
+            "code",
+            "pre",
+            "blockquote",
+            "ul",
+            "ol",
+            "li",
+            "a",
+            "img",
+        ]))
+        .tag_attributes(HashMap::from([
+            ("a", HashSet::from(["href"])),
+            ("img", HashSet::from(["src", "alt"])),
+            ("code", HashSet::from(["class"])),
+        ]))
+        .generic_attributes(HashSet::new())
+        .url_schemes(HashSet::from(LINK_SCHEMES))
+        .url_relative(UrlRelative::PassThrough)
+        .link_rel(Some("noopener noreferrer nofollow"));
+    builder
+});
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RenderedContent {
+    pub(crate) html: String,
+    pub(crate) plain_text: String,
+}
+
+/// Validate the content of a message and render it to sanitized html and plain text.
+/// Attachments are referenced by their `/files/...` url and must be among the files of the message.
+pub(crate) fn render_content(
+    content: &str,
+    format: ContentFormat,
+    files: &[String],
+) -> Result<RenderedContent, AppError> {
+    match format {
+        ContentFormat::Plain => Ok(render_plain(content)),
+        ContentFormat::Markdown => render_markdown(content, files),
+    }
+}
+
+fn render_plain(content: &str) -> RenderedContent {
+    let html = if content.is_empty() {
+        String::new()
+    } else {
+        // keep in sync with the backfill of the message_format migration
+        format!("<p>{}</p>", escape_html(content).replace('\n', "<br>"))
+    };
+    RenderedContent {
+        html,
+        plain_text: content.to_string(),
+    }
+}
+
+fn render_markdown(content: &str, files: &[String]) -> Result<RenderedContent, AppError> {
+    let mut events = vec![];
+    let mut plain_text = String::new();
+    for event in Parser::new_ext(content, Options::ENABLE_STRIKETHROUGH) {
+        let event = match event {
+            Event::Start(Tag::Link { ref dest_url, .. }) => {
+                verify_link(dest_url, files)?;
+                event
+            }
+            Event::Start(Tag::Image { ref dest_url, .. }) => {
+                verify_attachment(dest_url, files)?;
+                event
+            }
+            // raw html is shown as typed
+            Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+            event => event,
+        };
+        match &event {
+            Event::Text(text) | Event::Code(text) => plain_text.push_str(text),
+            Event::SoftBreak | Event::HardBreak => plain_text.push('\n'),
+            Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Heading(_)
+                | TagEnd::CodeBlock
+                | TagEnd::BlockQuote(_)
+                | TagEnd::Item,
+            )
+            | Event::Rule
+                if !plain_text.is_empty() && !plain_text.ends_with('\n') =>
+            {
+                plain_text.push('\n')
+            }
+            _ => {}
+        }
+        events.push(event);
+    }
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, events.into_iter());
+    Ok(RenderedContent {
+        html: SANITIZER.clean(&unsafe_html).to_string(),
+        plain_text: plain_text.trim_end().to_string(),
+    })
+}
+
+fn verify_link(url: &str, files: &[String]) -> Result<(), AppError> {
+    if url.starts_with("/files/") {
+        return verify_attachment(url, files);
+    }
+    match url.split_once(':') {
+        Some((scheme, _)) if LINK_SCHEMES.contains(&scheme.to_ascii_lowercase().as_str()) => Ok(()),
+        _ => Err(AppError::ContentError(format!("Unsupported link: {url}"))),
+    }
+}
+
+fn verify_attachment(url: &str, files: &[String]) -> Result<(), AppError> {
+    if files.iter().any(|file| file == url) {
+        return Ok(());
+    }
+    Err(AppError::ContentError(format!(
+        "Attachment {url} must be one of the files of the message"
+    )))
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    const FILE: &str = "/files/1/0a0/a9f/2a6772942557ab5355d76af442f8f65e01.png";
+
+    fn markdown(content: &str) -> Result<RenderedContent, AppError> {
+        render_content(content, ContentFormat::Markdown, &[FILE.to_string()])
+    }
+
+    #[test]
+    fn render_plain_should_escape() {
+        let rendered = render_content("<b>hi</b>\n& bye", ContentFormat::Plain, &[]).unwrap();
+        assert_eq!(rendered.html, "<p>&lt;b&gt;hi&lt;/b&gt;<br>&amp; bye</p>");
+        assert_eq!(rendered.plain_text, "<b>hi</b>\n& bye");
+    }
+
+    #[test]
+    fn render_markdown_should_work() -> Result<()> {
+        let rendered =
+            markdown("> **hi** [there](https://example.com)\n\n```rust\nlet a = 1;\n```")?;
+        assert_eq!(
+            rendered.html,
+            "<blockquote>\n<p><strong>hi</strong> <a href=\"https://example.com\" rel=\"noopener noreferrer nofollow\">there</a></p>\n</blockquote>\n<pre><code class=\"language-rust\">let a = 1;\n</code></pre>\n"
+        );
+        assert_eq!(rendered.plain_text, "hi there\nlet a = 1;");
+
+        let rendered = markdown(&format!("![chart]({FILE})"))?;
+        assert_eq!(
+            rendered.html,
+            format!("<p><img src=\"{FILE}\" alt=\"chart\"></p>\n")
+        );
+        assert_eq!(rendered.plain_text, "chart");
+        Ok(())
+    }
+
+    #[test]
+    fn render_markdown_should_sanitize() -> Result<()> {
+        let rendered =
+            markdown("<script>alert(1)</script> <img src=x onerror=alert(1)>\n\n# title")?;
+        assert_eq!(
+            rendered.html,
+            "&lt;script&gt;alert(1)&lt;/script&gt; &lt;img src=x onerror=alert(1)&gt;\ntitle\n"
+        );
+
+        assert!(markdown("[click](javascript:alert(1))").is_err());
+        assert!(markdown("[relative](../admin)").is_err());
+        assert!(markdown("![remote](https://example.com/a.png)").is_err());
+        assert!(markdown("[other](/files/1/abc/def/0123.png)").is_err());
+        Ok(())
+    }
+}
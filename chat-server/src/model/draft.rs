@@ -0,0 +1,129 @@
+use crate::{AppError, AppState};
+use chat_core::Draft;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct SaveDraft {
+    #[serde(default)]
+    pub content: String,
+    /// uploaded files not sent yet
+    #[serde(default)]
+    pub files: Vec<String>,
+}
+
+impl AppState {
+    /// Save the draft of the user in the chat, replacing the previous one.
+    pub async fn save_draft(
+        &self,
+        input: SaveDraft,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<Draft, AppError> {
+        if input.content.is_empty() && input.files.is_empty() {
+            return Err(AppError::DraftError(
+                "Content or files must be provided".to_string(),
+            ));
+        }
+        self.verify_files(&input.files, AppError::DraftError)?;
+        self.verify_chat_writable(chat_id, AppError::DraftError)
+            .await?;
+
+        let draft = sqlx::query_as(
+            r#"
+            INSERT INTO chat_drafts (chat_id, user_id, content, files)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (chat_id, user_id)
+            DO UPDATE SET content = EXCLUDED.content, files = EXCLUDED.files, updated_at = NOW()
+            RETURNING chat_id, user_id, content, files, updated_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(input.content)
+        .bind(&input.files)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(draft)
+    }
+
+    pub async fn get_draft(&self, chat_id: u64, user_id: u64) -> Result<Option<Draft>, AppError> {
+        let draft = sqlx::query_as(
+            r#"
+            SELECT chat_id, user_id, content, files, updated_at
+            FROM chat_drafts
+            WHERE chat_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(draft)
+    }
+
+    /// Discard the draft of the user in the chat, discarding a missing draft is a no-op.
+    pub async fn delete_draft(&self, chat_id: u64, user_id: u64) -> Result<(), AppError> {
+        sqlx::query("DELETE FROM chat_drafts WHERE chat_id = $1 AND user_id = $2")
+            .bind(chat_id as i64)
+            .bind(user_id as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn drafts_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = SaveDraft {
+            content: "half written".to_string(),
+            files: vec![],
+        };
+        state.save_draft(input, 1, 2).await?;
+        let input = SaveDraft {
+            content: "almost done".to_string(),
+            files: vec![],
+        };
+        let draft = state.save_draft(input, 1, 2).await?;
+        assert_eq!(draft.content, "almost done");
+        assert_eq!(state.get_draft(1, 2).await?, Some(draft));
+        // drafts are private to the user
+        assert_eq!(state.get_draft(1, 1).await?, None);
+
+        let chats = state.fetch_chats(2, 1).await?;
+        let chat = chats.iter().find(|c| c.id == 1).expect("chat should exist");
+        let draft = chat.draft.as_ref().expect("draft should exist");
+        assert_eq!(draft.content, "almost done");
+        assert!(chats
+            .iter()
+            .filter(|c| c.id != 1)
+            .all(|c| c.draft.is_none()));
+
+        let err = state
+            .save_draft(SaveDraft::default(), 1, 2)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::DraftError(_)));
+        let input = SaveDraft {
+            content: "".to_string(),
+            files: vec!["/files/1/abc/def/0123.png".to_string()],
+        };
+        let err = state.save_draft(input, 1, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::DraftError(_)));
+
+        state.delete_draft(1, 2).await?;
+        assert_eq!(state.get_draft(1, 2).await?, None);
+        state.delete_draft(1, 2).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+use super::ChatFile;
+use crate::{AppError, AppState};
+use std::io::ErrorKind;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::fs;
+use tokio::time::interval;
+use tracing::{info, warn};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+impl AppState {
+    /// Remove the expired messages for good along with their replies, then remove the uploads
+    /// no other message refers to. Returns how many messages were removed.
+    /// The `add_to_message` trigger notifies the chat members of every removed message.
+    pub(crate) async fn delete_expired_messages(&self) -> Result<usize, AppError> {
+        let deleted: Vec<(i64, Vec<String>)> = sqlx::query_as(
+            r#"
+            WITH expired AS (
+                SELECT id FROM messages WHERE expires_at <= NOW()
+            )
+            DELETE FROM messages
+            WHERE id IN (SELECT id FROM expired) OR parent_id IN (SELECT id FROM expired)
+            RETURNING id, files
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let files: Vec<String> = deleted
+            .iter()
+            .flat_map(|(_, files)| files.iter().cloned())
+            .collect();
+        if !files.is_empty() {
+            self.delete_unreferenced_files(&files).await?;
+        }
+        Ok(deleted.len())
+    }
+
+    async fn delete_unreferenced_files(&self, files: &[String]) -> Result<(), AppError> {
+        let files: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT DISTINCT f
+            FROM unnest($1::text[]) f
+            WHERE NOT EXISTS (SELECT 1 FROM messages WHERE f = ANY(files) OR quote->'files' ? f)
+                AND NOT EXISTS (SELECT 1 FROM message_revisions WHERE f = ANY(files))
+                AND NOT EXISTS (SELECT 1 FROM scheduled_messages WHERE f = ANY(files))
+                AND NOT EXISTS (SELECT 1 FROM chat_drafts WHERE f = ANY(files))
+            "#,
+        )
+        .bind(files)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let base_dir = &self.config.server.base_url;
+        for file in files {
+            let path = match ChatFile::from_str(&file) {
+                Ok(chat_file) => chat_file.path(base_dir),
+                Err(e) => {
+                    warn!("Skip invalid file {}: {}", file, e);
+                    continue;
+                }
+            };
+            match fs::remove_file(&path).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    warn!("Failed to remove file {}: {}", path.display(), e)
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Periodically remove the expired messages of the chats with a message ttl.
+pub fn setup_expiry_sweeper(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = interval(SWEEP_INTERVAL);
+        loop {
+            ticker.tick().await;
+            match state.delete_expired_messages().await {
+                Ok(0) => {}
+                Ok(deleted) => info!("Deleted {} expired messages", deleted),
+                Err(e) => warn!("Failed to delete expired messages: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{CreateMessage, SaveDraft, UpdateChat};
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn expired_messages_should_be_deleted() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = UpdateChat {
+            name: None,
+            members: None,
+            chat_type: None,
+            message_ttl: Some(3600),
+        };
+        let chat = state.update_chat_by_id(1, input, 1).await?;
+        assert_eq!(chat.message_ttl, Some(3600));
+
+        let file = ChatFile::new(1, "expire.txt", b"disappearing upload");
+        let path = file.path(&state.config.server.base_url);
+        std::fs::create_dir_all(path.parent().expect("file path parent should exists"))?;
+        std::fs::write(&path, b"disappearing upload")?;
+        let input = CreateMessage {
+            content: "see you never".to_string(),
+            files: vec![file.url()],
+            ..Default::default()
+        };
+        let message = state.create_message(input, 1, 1).await?;
+        assert!(message.expires_at.is_some());
+        let input = CreateMessage {
+            content: "reply".to_string(),
+            parent_id: Some(message.id as _),
+            ..Default::default()
+        };
+        state.create_message(input, 1, 2).await?;
+        assert_eq!(state.delete_expired_messages().await?, 0);
+
+        sqlx::query("UPDATE messages SET expires_at = NOW() WHERE id = $1")
+            .bind(message.id)
+            .execute(&state.pool)
+            .await?;
+        // the reply goes away with its expired root
+        assert_eq!(state.delete_expired_messages().await?, 2);
+        assert!(!path.exists());
+
+        let input = UpdateChat {
+            name: None,
+            members: None,
+            chat_type: None,
+            message_ttl: Some(0),
+        };
+        let chat = state.update_chat_by_id(1, input, 1).await?;
+        assert_eq!(chat.message_ttl, None);
+        let input = CreateMessage {
+            content: "stay".to_string(),
+            ..Default::default()
+        };
+        let message = state.create_message(input, 1, 1).await?;
+        assert!(message.expires_at.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn files_in_drafts_should_be_kept() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let file = ChatFile::new(1, "draft.txt", b"still drafting");
+        let path = file.path(&state.config.server.base_url);
+        std::fs::create_dir_all(path.parent().expect("file path parent should exists"))?;
+        std::fs::write(&path, b"still drafting")?;
+        let input = CreateMessage {
+            content: "first try".to_string(),
+            files: vec![file.url()],
+            ..Default::default()
+        };
+        let message = state.create_message(input, 1, 1).await?;
+        let input = SaveDraft {
+            content: "second try".to_string(),
+            files: vec![file.url()],
+        };
+        state.save_draft(input, 1, 1).await?;
+
+        sqlx::query("UPDATE messages SET expires_at = NOW() WHERE id = $1")
+            .bind(message.id)
+            .execute(&state.pool)
+            .await?;
+        assert_eq!(state.delete_expired_messages().await?, 1);
+        assert!(path.exists());
+
+        Ok(())
+    }
+}
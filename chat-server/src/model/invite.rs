@@ -0,0 +1,224 @@
+use crate::{AppError, AppState};
+use chat_core::{Chat, ChatInvite, ChatType};
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct CreateInvite {
+    /// when the invite stops working, never by default
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// how many users can accept the invite, unlimited by default
+    #[serde(default)]
+    pub max_uses: Option<u32>,
+}
+
+impl AppState {
+    /// Create an invite code for the channel.
+    pub async fn create_invite(
+        &self,
+        input: CreateInvite,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<ChatInvite, AppError> {
+        let chat = self
+            .get_chat_by_id(chat_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))?;
+        if !matches!(
+            chat.r#type,
+            ChatType::PrivateChannel | ChatType::PublicChannel
+        ) {
+            return Err(AppError::InviteError(
+                "Only channels can have invites".to_string(),
+            ));
+        }
+        if chat.archived_at.is_some() {
+            return Err(AppError::InviteError(
+                "Cannot change an archived chat".to_string(),
+            ));
+        }
+        if input.expires_at.is_some_and(|at| at <= Utc::now()) {
+            return Err(AppError::InviteError(
+                "expires_at must be in the future".to_string(),
+            ));
+        }
+        if input.max_uses == Some(0) || input.max_uses.is_some_and(|n| n > i32::MAX as u32) {
+            return Err(AppError::InviteError(
+                "max_uses must be a positive number".to_string(),
+            ));
+        }
+
+        let invite = sqlx::query_as(
+            r#"
+            INSERT INTO chat_invites (chat_id, code, created_by, expires_at, max_uses)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, chat_id, code, created_by, expires_at, max_uses, uses, revoked_at, created_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(Uuid::new_v4().simple().to_string())
+        .bind(user_id as i64)
+        .bind(input.expires_at)
+        .bind(input.max_uses.map(|n| n as i32))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(invite)
+    }
+
+    /// List the invites of the chat which are not revoked, the newest first.
+    pub async fn list_invites(&self, chat_id: u64) -> Result<Vec<ChatInvite>, AppError> {
+        let invites = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, code, created_by, expires_at, max_uses, uses, revoked_at, created_at
+            FROM chat_invites
+            WHERE chat_id = $1 AND revoked_at IS NULL
+            ORDER BY id DESC
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(invites)
+    }
+
+    /// Revoke the invite, the code can not be accepted afterwards.
+    pub async fn revoke_invite(&self, chat_id: u64, invite_id: u64) -> Result<(), AppError> {
+        let ret = sqlx::query(
+            r#"
+            UPDATE chat_invites
+            SET revoked_at = NOW()
+            WHERE id = $1 AND chat_id = $2 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(invite_id as i64)
+        .bind(chat_id as i64)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("invite id {invite_id}")));
+        }
+
+        Ok(())
+    }
+
+    /// Accept the invite, the user joins the chat as a plain member.
+    /// Accepting an invite to a chat the user is already in does not use it up.
+    pub async fn accept_invite(
+        &self,
+        code: &str,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Chat, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let invite: Option<ChatInvite> = sqlx::query_as(
+            r#"
+            SELECT i.id, i.chat_id, i.code, i.created_by, i.expires_at, i.max_uses, i.uses,
+                i.revoked_at, i.created_at
+            FROM chat_invites i
+            JOIN chats c ON c.id = i.chat_id
+            WHERE i.code = $1 AND c.ws_id = $2
+            FOR UPDATE OF i
+            "#,
+        )
+        .bind(code)
+        .bind(ws_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        // do not reveal invites of other workspaces
+        let Some(invite) = invite else {
+            return Err(AppError::NotFound(format!("invite {code}")));
+        };
+        let chat_id = invite.chat_id as u64;
+        if !self.is_chat_member(chat_id, user_id).await? {
+            self.verify_chat_writable(chat_id, AppError::InviteError)
+                .await?;
+            self.use_invite(&mut tx, &invite, user_id).await?;
+        }
+        tx.commit().await?;
+
+        self.get_chat_by_id(chat_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))
+    }
+
+    async fn use_invite(
+        &self,
+        conn: &mut PgConnection,
+        invite: &ChatInvite,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        if invite.revoked_at.is_some() {
+            return Err(AppError::InviteError("The invite is revoked".to_string()));
+        }
+        if invite.expires_at.is_some_and(|at| at <= Utc::now()) {
+            return Err(AppError::InviteError("The invite is expired".to_string()));
+        }
+        if invite.max_uses.is_some_and(|n| invite.uses >= n) {
+            return Err(AppError::InviteError("The invite is used up".to_string()));
+        }
+
+        if self
+            .insert_chat_member(conn, invite.chat_id as _, user_id, invite.created_by as _)
+            .await?
+        {
+            sqlx::query("UPDATE chat_invites SET uses = uses + 1 WHERE id = $1")
+                .bind(invite.id)
+                .execute(conn)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn invites_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateInvite {
+            expires_at: Some(Utc::now() + Duration::from_secs(3600)),
+            max_uses: Some(1),
+        };
+        let invite = state.create_invite(input, 2, 1).await?;
+        assert_eq!(invite.code.len(), 32);
+
+        let chat = state.accept_invite(&invite.code, 4, 1).await?;
+        assert!(chat.members.contains(&4));
+        // members accepting again do not use up the invite
+        state.accept_invite(&invite.code, 4, 1).await?;
+        let err = state.accept_invite(&invite.code, 5, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::InviteError(_)));
+        // users of other workspaces do not see the invite
+        let err = state.accept_invite(&invite.code, 5, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        let invite = state.create_invite(CreateInvite::default(), 2, 1).await?;
+        assert_eq!(state.list_invites(2).await?.len(), 2);
+        state.revoke_invite(2, invite.id as _).await?;
+        assert_eq!(state.list_invites(2).await?.len(), 1);
+        let err = state.accept_invite(&invite.code, 5, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::InviteError(_)));
+        let err = state.revoke_invite(2, invite.id as _).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        // only channels can have invites
+        let err = state
+            .create_invite(CreateInvite::default(), 4, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::InviteError(_)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+use crate::{AppError, AppState};
+use chat_core::{ChatMember, ChatRole, ChatType};
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Debug, Clone, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct ListMembers {
+    /// list the members with a user id greater than this
+    #[serde(default)]
+    pub last_id: Option<u64>,
+    #[serde(default)]
+    pub limit: u64,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct UpdateMember {
+    /// admin or member, the ownership can not be handed over here
+    pub role: ChatRole,
+}
+
+impl AppState {
+    /// List the members of the chat with their roles, ordered by user id.
+    pub async fn list_chat_members(
+        &self,
+        chat_id: u64,
+        input: ListMembers,
+    ) -> Result<Vec<ChatMember>, AppError> {
+        let last_id = input.last_id.unwrap_or(0);
+        let limit = match input.limit {
+            0 => 20,
+            1..=100 => input.limit as _,
+            _ => 100,
+        };
+        let members = sqlx::query_as(
+            r#"
+            SELECT cm.user_id, u.fullname, u.email, cm.role, cm.joined_at, cm.added_by
+            FROM chat_members cm
+            JOIN users u ON u.id = cm.user_id
+            WHERE cm.chat_id = $1 AND cm.user_id > $2
+            ORDER BY cm.user_id
+            LIMIT $3
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(last_id as i64)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(members)
+    }
+
+    pub async fn get_chat_member(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<Option<ChatMember>, AppError> {
+        let member = sqlx::query_as(
+            r#"
+            SELECT cm.user_id, u.fullname, u.email, cm.role, cm.joined_at, cm.added_by
+            FROM chat_members cm
+            JOIN users u ON u.id = cm.user_id
+            WHERE cm.chat_id = $1 AND cm.user_id = $2
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(member)
+    }
+
+    /// Add the user to the chat as a plain member, returns false if the user is a member already.
+    /// The trigger on chat_members notifies the chat of the new member.
+    pub(crate) async fn insert_chat_member(
+        &self,
+        conn: &mut PgConnection,
+        chat_id: u64,
+        user_id: u64,
+        added_by: u64,
+    ) -> Result<bool, AppError> {
+        let ret = sqlx::query(
+            r#"
+            INSERT INTO chat_members (chat_id, user_id, added_by)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(added_by as i64)
+        .execute(conn)
+        .await?;
+
+        Ok(ret.rows_affected() > 0)
+    }
+
+    /// Add a user of the chat's workspace to the chat, adding a member again is a no-op.
+    pub async fn add_chat_member(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+        added_by: u64,
+    ) -> Result<ChatMember, AppError> {
+        let chat = self
+            .get_chat_by_id(chat_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))?;
+        if chat.r#type == ChatType::Single {
+            return Err(AppError::UpdateChatError(
+                "Cannot update members of a single chat".to_string(),
+            ));
+        }
+        if chat.archived_at.is_some() {
+            return Err(AppError::UpdateChatError(
+                "Cannot change an archived chat".to_string(),
+            ));
+        }
+        let user = sqlx::query("SELECT 1 FROM users WHERE id = $1 AND ws_id = $2")
+            .bind(user_id as i64)
+            .bind(chat.ws_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        if user.is_none() {
+            return Err(AppError::NotFound(format!("user id {user_id}")));
+        }
+
+        let mut conn = self.pool.acquire().await?;
+        self.insert_chat_member(&mut conn, chat_id, user_id, added_by)
+            .await?;
+
+        self.get_chat_member(chat_id, user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("member id {user_id}")))
+    }
+
+    /// Change the role of a member, the owner keeps its role.
+    pub async fn update_chat_member(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+        input: UpdateMember,
+    ) -> Result<ChatMember, AppError> {
+        if input.role == ChatRole::Owner {
+            return Err(AppError::UpdateChatError(
+                "Only the admin or member role can be given".to_string(),
+            ));
+        }
+        self.verify_chat_writable(chat_id, AppError::UpdateChatError)
+            .await?;
+        let member = self
+            .get_chat_member(chat_id, user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("member id {user_id}")))?;
+        if member.role == ChatRole::Owner {
+            return Err(AppError::PermissionDenied(
+                "The role of the chat owner can not be changed".to_string(),
+            ));
+        }
+
+        sqlx::query("UPDATE chat_members SET role = $3 WHERE chat_id = $1 AND user_id = $2")
+            .bind(chat_id as i64)
+            .bind(user_id as i64)
+            .bind(input.role)
+            .execute(&self.pool)
+            .await?;
+
+        self.get_chat_member(chat_id, user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("member id {user_id}")))
+    }
+
+    /// Remove a member from the chat, the owner can only leave on its own.
+    pub async fn remove_chat_member(&self, chat_id: u64, user_id: u64) -> Result<(), AppError> {
+        let chat = self
+            .get_chat_by_id(chat_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))?;
+        if chat.r#type == ChatType::Single {
+            return Err(AppError::UpdateChatError(
+                "Cannot update members of a single chat".to_string(),
+            ));
+        }
+        let member = self
+            .get_chat_member(chat_id, user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("member id {user_id}")))?;
+        if member.role == ChatRole::Owner {
+            return Err(AppError::PermissionDenied(
+                "The chat owner can not be removed".to_string(),
+            ));
+        }
+
+        sqlx::query("DELETE FROM chat_members WHERE chat_id = $1 AND user_id = $2")
+            .bind(chat_id as i64)
+            .bind(user_id as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn list_chat_members_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = ListMembers {
+            last_id: None,
+            limit: 2,
+        };
+        let members = state.list_chat_members(1, input).await?;
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[0].user_id, 1);
+        assert_eq!(members[0].role, ChatRole::Owner);
+        assert_eq!(members[1].role, ChatRole::Member);
+
+        let input = ListMembers {
+            last_id: Some(members[1].user_id as _),
+            limit: 0,
+        };
+        let members = state.list_chat_members(1, input).await?;
+        let ids: Vec<_> = members.iter().map(|m| m.user_id).collect();
+        assert_eq!(ids, vec![3, 4, 5]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn add_and_remove_chat_member_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let member = state.add_chat_member(2, 4, 1).await?;
+        assert_eq!(member.user_id, 4);
+        assert_eq!(member.role, ChatRole::Member);
+        assert_eq!(member.added_by, Some(1));
+        assert!(state.is_chat_member(2, 4).await?);
+        // adding again keeps the membership as is
+        let member = state.add_chat_member(2, 4, 2).await?;
+        assert_eq!(member.added_by, Some(1));
+
+        state.remove_chat_member(2, 4).await?;
+        assert!(!state.is_chat_member(2, 4).await?);
+        let err = state.remove_chat_member(2, 4).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        let err = state.remove_chat_member(2, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        let err = state.add_chat_member(3, 4, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateChatError(_)));
+        let err = state.add_chat_member(2, 10, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_chat_member_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = UpdateMember {
+            role: ChatRole::Admin,
+        };
+        let member = state.update_chat_member(2, 2, input).await?;
+        assert_eq!(member.role, ChatRole::Admin);
+        assert!(state.is_chat_admin(2, 2).await?);
+
+        let input = UpdateMember {
+            role: ChatRole::Member,
+        };
+        state.update_chat_member(2, 2, input.clone()).await?;
+        assert!(!state.is_chat_admin(2, 2).await?);
+        let err = state.update_chat_member(2, 1, input).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        let input = UpdateMember {
+            role: ChatRole::Owner,
+        };
+        let err = state.update_chat_member(2, 2, input).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateChatError(_)));
+
+        Ok(())
+    }
+}
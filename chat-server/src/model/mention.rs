@@ -0,0 +1,242 @@
+use crate::{AppError, AppState};
+use chat_core::{Mention, MentionKind, Message};
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgConnection};
+use std::collections::{HashMap, HashSet};
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Debug, Clone, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct ListMentions {
+    #[serde(default)]
+    pub last_id: Option<u64>,
+    #[serde(default)]
+    pub limit: u64,
+}
+
+#[derive(Debug, FromRow)]
+struct MentionRow {
+    id: i64,
+    message_id: i64,
+    kind: MentionKind,
+    created_at: DateTime<Utc>,
+}
+
+impl AppState {
+    /// Resolve `@user`, `@here` and `@channel` in the content to the mentioned chat members.
+    /// `@user` is matched against the local part of the email of the workspace users.
+    /// Presence is only known to notify-server, so `@here` reaches every member like `@channel`,
+    /// the kind is kept for the clients to tell them apart.
+    pub(crate) async fn resolve_mentions(
+        &self,
+        chat_id: u64,
+        sender_id: u64,
+        content: &str,
+    ) -> Result<Vec<(i64, MentionKind)>, AppError> {
+        let handles = parse_mentions(content);
+        if handles.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let Some(chat) = self.get_chat_by_id(chat_id).await? else {
+            return Err(AppError::NotFound(format!("chat id {chat_id}")));
+        };
+        let users: HashMap<String, i64> = self
+            .fetch_chat_users(chat.ws_id as _)
+            .await?
+            .into_iter()
+            .filter_map(|user| {
+                let (name, _) = user.email.split_once('@')?;
+                Some((name.to_lowercase(), user.id))
+            })
+            .collect();
+
+        let mut mentions = vec![];
+        let mut mentioned = HashSet::from([sender_id as i64]);
+        for handle in &handles {
+            if let Some(id) = users.get(handle) {
+                if chat.members.contains(id) && mentioned.insert(*id) {
+                    mentions.push((*id, MentionKind::User));
+                }
+            }
+        }
+        for (handle, kind) in [
+            ("channel", MentionKind::Channel),
+            ("here", MentionKind::Here),
+        ] {
+            if !handles.iter().any(|h| h == handle) {
+                continue;
+            }
+            for id in &chat.members {
+                if mentioned.insert(*id) {
+                    mentions.push((*id, kind.clone()));
+                }
+            }
+        }
+        Ok(mentions)
+    }
+
+    pub(crate) async fn create_mentions(
+        &self,
+        conn: &mut PgConnection,
+        message: &Message,
+        mentions: &[(i64, MentionKind)],
+    ) -> Result<(), AppError> {
+        for (user_id, kind) in mentions {
+            sqlx::query(
+                r#"
+                INSERT INTO mentions (message_id, chat_id, user_id, kind)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT DO NOTHING
+                "#,
+            )
+            .bind(message.id)
+            .bind(message.chat_id)
+            .bind(user_id)
+            .bind(kind)
+            .execute(&mut *conn)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// List the mentions of the user in chats the user is still a member of, newest first.
+    pub async fn list_mentions(
+        &self,
+        input: ListMentions,
+        user_id: u64,
+    ) -> Result<Vec<Mention>, AppError> {
+        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+        let limit = match input.limit {
+            0 => 20,
+            1..=100 => input.limit as _,
+            _ => 100,
+        };
+        let rows: Vec<MentionRow> = sqlx::query_as(
+            r#"
+            SELECT mt.id, mt.message_id, mt.kind, mt.created_at
+            FROM mentions mt
+            JOIN messages m ON m.id = mt.message_id
+            JOIN chat_members cm ON cm.chat_id = mt.chat_id AND cm.user_id = $1
+            WHERE mt.user_id = $1 AND mt.id < $2 AND m.deleted_at IS NULL
+            ORDER BY mt.id DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(last_id as i64)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let ids: Vec<i64> = rows.iter().map(|row| row.message_id).collect();
+        let mut messages: Vec<Message> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, content, files, created_at, updated_at, deleted_at, parent_id, expires_at, quote, format, html, plain_text
+            FROM messages
+            WHERE id = ANY($1)
+            "#,
+        )
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await?;
+        self.attach_reactions(&mut messages, user_id).await?;
+        let mut messages: HashMap<i64, Message> = messages.into_iter().map(|m| (m.id, m)).collect();
+
+        let mentions = rows
+            .into_iter()
+            .filter_map(|row| {
+                Some(Mention {
+                    id: row.id,
+                    kind: row.kind,
+                    created_at: row.created_at,
+                    message: messages.remove(&row.message_id)?,
+                })
+            })
+            .collect();
+        Ok(mentions)
+    }
+}
+
+/// Extract the lowercased handles after `@`, an `@` inside a word (e.g. an email) is ignored.
+fn parse_mentions(content: &str) -> Vec<String> {
+    let mut handles = vec![];
+    let mut prev: Option<char> = None;
+    let mut chars = content.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let at_boundary = prev.is_none_or(|p| !p.is_alphanumeric() && p != '_');
+        prev = Some(c);
+        if c != '@' || !at_boundary {
+            continue;
+        }
+        let start = i + 1;
+        let mut end = start;
+        while let Some(&(j, c)) = chars.peek() {
+            if !(c.is_alphanumeric() || matches!(c, '.' | '_' | '-')) {
+                break;
+            }
+            end = j + c.len_utf8();
+            prev = Some(c);
+            chars.next();
+        }
+        let handle = content[start..end].trim_end_matches(['.', '-']);
+        if !handle.is_empty() {
+            let handle = handle.to_lowercase();
+            if !handles.contains(&handle) {
+                handles.push(handle);
+            }
+        }
+    }
+    handles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::CreateMessage;
+    use anyhow::Result;
+
+    #[test]
+    fn parse_mentions_should_work() {
+        let handles = parse_mentions("@Alice hi, ping @bob. and @here (mail wu@github.org)");
+        assert_eq!(handles, vec!["alice", "bob", "here"]);
+        assert!(parse_mentions("no mentions @ all").is_empty());
+    }
+
+    #[tokio::test]
+    async fn mentions_should_be_listed_in_inbox() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "@bob @daisy please review".to_string(),
+            ..Default::default()
+        };
+        // daisy is not a member of chat 2
+        let message = state.create_message(input, 2, 1).await?;
+
+        let input = ListMentions {
+            last_id: None,
+            limit: 10,
+        };
+        let mentions = state.list_mentions(input.clone(), 3).await?;
+        assert_eq!(mentions.len(), 1);
+        assert_eq!(mentions[0].kind, MentionKind::User);
+        assert_eq!(mentions[0].message.id, message.id);
+        assert!(state.list_mentions(input.clone(), 5).await?.is_empty());
+
+        let input2 = CreateMessage {
+            content: "hey @channel and @bob".to_string(),
+            ..Default::default()
+        };
+        state.create_message(input2, 2, 1).await?;
+        let mentions = state.list_mentions(input.clone(), 2).await?;
+        assert_eq!(mentions.len(), 1);
+        assert_eq!(mentions[0].kind, MentionKind::Channel);
+        let mentions = state.list_mentions(input.clone(), 3).await?;
+        assert_eq!(mentions.len(), 2);
+        assert_eq!(mentions[0].kind, MentionKind::User);
+        // the sender is never mentioned
+        assert!(state.list_mentions(input, 1).await?.is_empty());
+
+        Ok(())
+    }
+}
@@ -1,25 +1,109 @@
-use super::ChatFile;
+use super::content::render_content;
+use super::poll::verify_poll;
+use super::{ChatFile, CreatePoll};
 use crate::{AppError, AppState};
-use chat_core::Message;
+use chat_core::{ContentFormat, Message, MessageQuote, MessageRevision};
 use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::types::Json;
+use sqlx::PgConnection;
 use std::str::FromStr;
 use utoipa::{IntoParams, ToSchema};
 
-#[derive(Debug, Clone, IntoParams, ToSchema, Serialize, Deserialize)]
+const MAX_FORWARD_CHATS: usize = 10;
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: u64 = 100;
+const MAX_NONCE_LEN: usize = 64;
+/// retries with the same nonce are deduped within this window
+const NONCE_WINDOW_SECS: i32 = 24 * 3600;
+
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
 pub struct CreateMessage {
     pub content: String,
     #[serde(default)]
     pub files: Vec<String>,
+    /// reply to a message, the reply is attached to the root of its thread
+    #[serde(default)]
+    pub parent_id: Option<u64>,
+    /// deliver the message at this time instead of now
+    #[serde(default)]
+    pub send_at: Option<DateTime<Utc>>,
+    /// quote a message of any chat the sender is a member of
+    #[serde(default)]
+    pub quote_of: Option<u64>,
+    /// send the message as a poll
+    #[serde(default)]
+    pub poll: Option<CreatePoll>,
+    /// how the content is rendered, plain by default
+    #[serde(default)]
+    pub format: ContentFormat,
+    /// sending again with the same nonce returns the original message instead of a duplicate
+    #[serde(default)]
+    pub client_nonce: Option<String>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct ForwardMessage {
+    pub chat_ids: Vec<u64>,
+    /// optional comment sent along with the forwarded message
+    #[serde(default)]
+    pub comment: String,
 }
 
 #[derive(Debug, Clone, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct UpdateMessage {
+    pub content: Option<String>,
+    pub files: Option<Vec<String>>,
+    /// keep the current format if not provided
+    #[serde(default)]
+    pub format: Option<ContentFormat>,
+}
+
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
 pub struct ListMessages {
+    /// deprecated, the id of the oldest message seen, use `before` instead
     #[serde(default)]
     pub last_id: Option<u64>,
+    /// cursor of a page, list the messages older than it
+    #[serde(default)]
+    pub before: Option<String>,
+    /// cursor of a page, list the messages newer than it
+    #[serde(default)]
+    pub after: Option<String>,
+    /// message id, list the messages around it including itself
+    #[serde(default)]
+    pub around: Option<u64>,
     #[serde(default)]
     pub limit: u64,
 }
 
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct GetMessageContext {
+    /// size of the page around the message
+    #[serde(default)]
+    pub limit: u64,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct MessagePage {
+    /// newest first
+    pub messages: Vec<Message>,
+    /// pass as `before` to get the older messages
+    pub before_cursor: Option<String>,
+    /// pass as `after` to get the newer messages
+    pub after_cursor: Option<String>,
+    pub has_more_before: bool,
+    pub has_more_after: bool,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct MessageContext {
+    pub chat_id: i64,
+    /// the thread the message replies to
+    pub parent_id: Option<i64>,
+    pub page: MessagePage,
+}
+
 #[allow(dead_code)]
 impl AppState {
     pub async fn create_message(
@@ -28,64 +112,655 @@ impl AppState {
         chat_id: u64,
         user_id: u64,
     ) -> Result<Message, AppError> {
-        let base_dir = &self.config.server.base_url;
-        if input.content.is_empty() && input.files.is_empty() {
-            return Err(AppError::CreateMessageError(
-                "Content or files must be provided".to_string(),
-            ));
+        // a retried send may return a message with a poll
+        let attach_polls = input.poll.is_some() || input.client_nonce.is_some();
+        let mut tx = self.pool.begin().await?;
+        let mut message = self
+            .insert_message(&mut tx, input, chat_id, user_id)
+            .await?;
+        tx.commit().await?;
+        if attach_polls {
+            self.attach_polls(std::slice::from_mut(&mut message), user_id)
+                .await?;
         }
-        for s in &input.files {
-            let file = ChatFile::from_str(s)?;
-            if !file.path(base_dir).exists() {
-                return Err(AppError::CreateMessageError(format!(
-                    "Invalid chat file path: {}",
-                    s
-                )));
+
+        Ok(message)
+    }
+
+    /// Verify and insert the message on the connection, the caller owns the transaction.
+    pub(crate) async fn insert_message(
+        &self,
+        conn: &mut PgConnection,
+        input: CreateMessage,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<Message, AppError> {
+        let (parent_id, quote) = self.verify_new_message(&input, chat_id, user_id).await?;
+        let content = match &input.poll {
+            Some(poll) if input.content.is_empty() => poll.question.clone(),
+            _ => input.content,
+        };
+        let rendered = render_content(&content, input.format, &input.files)?;
+        let mentions = self.resolve_mentions(chat_id, user_id, &content).await?;
+
+        if let Some(nonce) = &input.client_nonce {
+            // serialize the retries of the same send, the first one wins
+            sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))")
+                .bind(format!("message:{chat_id}:{user_id}:{nonce}"))
+                .execute(&mut *conn)
+                .await?;
+            let sent: Option<Message> = sqlx::query_as(
+                r#"
+                SELECT id, chat_id, sender_id, content, files, created_at, updated_at, deleted_at, parent_id, expires_at, quote, format, html, plain_text, client_nonce
+                FROM messages
+                WHERE chat_id = $1 AND sender_id = $2 AND client_nonce = $3
+                    AND created_at > NOW() - make_interval(secs => $4)
+                ORDER BY id DESC
+                LIMIT 1
+                "#,
+            )
+            .bind(chat_id as i64)
+            .bind(user_id as i64)
+            .bind(nonce)
+            .bind(NONCE_WINDOW_SECS)
+            .fetch_optional(&mut *conn)
+            .await?;
+            if let Some(message) = sent {
+                return Ok(message);
             }
         }
         let message: Message = sqlx::query_as(
             r#"
-            INSERT INTO messages (chat_id, sender_id, content, files)
-            VALUES ($1, $2, $3, $4)
-            RETURNING id, chat_id, sender_id, content, files, created_at
+            INSERT INTO messages (chat_id, sender_id, content, files, parent_id, expires_at, quote,
+                format, html, plain_text, client_nonce)
+            SELECT $1, $2, $3, $4, $5, NOW() + make_interval(secs => message_ttl), $6, $7, $8, $9, $10
+            FROM chats
+            WHERE id = $1
+            RETURNING id, chat_id, sender_id, content, files, created_at, updated_at, deleted_at, parent_id, expires_at, quote, format, html, plain_text, client_nonce
             "#,
         )
         .bind(chat_id as i64)
         .bind(user_id as i64)
-        .bind(input.content)
+        .bind(content)
         .bind(&input.files)
-        .fetch_one(&self.pool)
+        .bind(parent_id)
+        .bind(quote.map(Json))
+        .bind(input.format)
+        .bind(rendered.html)
+        .bind(rendered.plain_text)
+        .bind(&input.client_nonce)
+        .fetch_one(&mut *conn)
         .await?;
+        self.create_mentions(&mut *conn, &message, &mentions)
+            .await?;
+        if let Some(poll) = &input.poll {
+            self.create_poll(conn, message.id, poll).await?;
+        }
+
         Ok(message)
     }
 
+    /// List the messages of the chat, newest first.
     pub async fn list_message(
         &self,
         input: ListMessages,
         chat_id: u64,
-    ) -> Result<Vec<Message>, AppError> {
-        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+        user_id: u64,
+    ) -> Result<MessagePage, AppError> {
+        self.list_page(input, chat_id, None, user_id).await
+    }
+
+    /// List the replies of a thread, with the same cursor semantics as `list_message`.
+    pub async fn list_thread(
+        &self,
+        input: ListMessages,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<MessagePage, AppError> {
+        let root = sqlx::query(
+            "SELECT 1 FROM messages WHERE id = $1 AND chat_id = $2 AND parent_id IS NULL",
+        )
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        if root.is_none() {
+            return Err(AppError::NotFound(format!("thread id {message_id}")));
+        }
+
+        self.list_page(input, chat_id, Some(message_id as i64), user_id)
+            .await
+    }
+
+    /// Resolve a permalink, the message is returned within a page of its chat or thread.
+    pub async fn get_message_context(
+        &self,
+        message_id: u64,
+        input: GetMessageContext,
+        user_id: u64,
+    ) -> Result<MessageContext, AppError> {
+        let row: Option<(i64, Option<i64>)> =
+            sqlx::query_as("SELECT chat_id, parent_id FROM messages WHERE id = $1")
+                .bind(message_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        // do not reveal messages of chats the user can not read
+        let (chat_id, parent_id) = match row {
+            Some((chat_id, parent_id))
+                if self.is_chat_member(chat_id as _, user_id).await?
+                    || self.can_preview_chat(chat_id as _, user_id).await? =>
+            {
+                (chat_id, parent_id)
+            }
+            _ => return Err(AppError::NotFound(format!("message id {message_id}"))),
+        };
+
+        let input = ListMessages {
+            around: Some(message_id),
+            limit: input.limit,
+            ..Default::default()
+        };
+        let page = self
+            .list_page(input, chat_id as _, parent_id, user_id)
+            .await?;
+        Ok(MessageContext {
+            chat_id,
+            parent_id,
+            page,
+        })
+    }
+
+    /// A page of the top level messages of the chat, or of the replies when `parent_id` is set.
+    async fn list_page(
+        &self,
+        input: ListMessages,
+        chat_id: u64,
+        parent_id: Option<i64>,
+        user_id: u64,
+    ) -> Result<MessagePage, AppError> {
         let limit = match input.limit {
-            0 => i64::MAX,
-            1..=100 => input.limit as _,
-            _ => 100,
+            0 => DEFAULT_PAGE_SIZE,
+            1..=MAX_PAGE_SIZE => input.limit as _,
+            _ => MAX_PAGE_SIZE as _,
         };
-        let messages: Vec<Message> = sqlx::query_as(
+        let before = match (input.before.as_deref(), input.last_id) {
+            (Some(cursor), None) => Some(decode_cursor(cursor)?),
+            (None, last_id) => last_id.map(|id| id as i64),
+            (Some(_), Some(_)) => {
+                return Err(AppError::CursorError(
+                    "last_id and before can not be used together".to_string(),
+                ))
+            }
+        };
+        let after = input.after.as_deref().map(decode_cursor).transpose()?;
+        let around = input.around.map(|id| id as i64);
+        if [before.is_some(), after.is_some(), around.is_some()]
+            .iter()
+            .filter(|set| **set)
+            .count()
+            > 1
+        {
+            return Err(AppError::CursorError(
+                "Only one of before, after and around can be used".to_string(),
+            ));
+        }
+
+        let mut messages = match (after, around) {
+            (Some(after), _) => {
+                let mut messages = self
+                    .fetch_messages(chat_id, parent_id, None, Some(after), limit)
+                    .await?;
+                messages.reverse();
+                messages
+            }
+            (_, Some(around)) => {
+                // the message itself counts to the older half
+                let mut messages = self
+                    .fetch_messages(chat_id, parent_id, None, Some(around), limit / 2)
+                    .await?;
+                messages.reverse();
+                let older = self
+                    .fetch_messages(
+                        chat_id,
+                        parent_id,
+                        Some(around + 1),
+                        None,
+                        limit - limit / 2,
+                    )
+                    .await?;
+                messages.extend(older);
+                messages
+            }
+            _ => {
+                self.fetch_messages(chat_id, parent_id, before, None, limit)
+                    .await?
+            }
+        };
+
+        let (has_more_before, has_more_after) = match (messages.last(), messages.first()) {
+            (Some(oldest), Some(newest)) => {
+                sqlx::query_as(
+                    r#"
+                    SELECT
+                        EXISTS(SELECT 1 FROM messages WHERE chat_id = $1
+                            AND parent_id IS NOT DISTINCT FROM $2 AND id < $3),
+                        EXISTS(SELECT 1 FROM messages WHERE chat_id = $1
+                            AND parent_id IS NOT DISTINCT FROM $2 AND id > $4)
+                    "#,
+                )
+                .bind(chat_id as i64)
+                .bind(parent_id)
+                .bind(oldest.id)
+                .bind(newest.id)
+                .fetch_one(&self.pool)
+                .await?
+            }
+            _ => (false, false),
+        };
+        // an empty page keeps the cursors, so a client can poll for newer messages
+        let before_cursor = match messages.last() {
+            Some(oldest) => Some(encode_cursor(oldest.id)),
+            None => input.before,
+        };
+        let after_cursor = match messages.first() {
+            Some(newest) => Some(encode_cursor(newest.id)),
+            None => input.after,
+        };
+
+        self.attach_reactions(&mut messages, user_id).await?;
+        self.attach_polls(&mut messages, user_id).await?;
+        Ok(MessagePage {
+            messages,
+            before_cursor,
+            after_cursor,
+            has_more_before,
+            has_more_after,
+        })
+    }
+
+    /// Messages older than `before` newest first, or newer than `after` oldest first.
+    async fn fetch_messages(
+        &self,
+        chat_id: u64,
+        parent_id: Option<i64>,
+        before: Option<i64>,
+        after: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<Message>, AppError> {
+        let messages = sqlx::query_as(
             r#"
-            SELECT id, chat_id, sender_id, content, files, created_at
-            FROM messages
-            WHERE chat_id = $1 AND id < $2
-            ORDER BY id DESC
-            LIMIT $3
+            SELECT m.id, m.chat_id, m.sender_id, m.content, m.files, m.created_at, m.updated_at,
+                m.deleted_at, m.parent_id, m.expires_at, m.quote, m.format, m.html, m.plain_text, r.reply_count, r.last_reply_at
+            FROM messages m
+            LEFT JOIN LATERAL (
+                SELECT COUNT(*) AS reply_count, MAX(created_at) AS last_reply_at
+                FROM messages
+                WHERE parent_id = m.id AND deleted_at IS NULL
+            ) r ON TRUE
+            WHERE m.chat_id = $1 AND m.parent_id IS NOT DISTINCT FROM $2
+                AND ($3::bigint IS NULL OR m.id < $3)
+                AND ($4::bigint IS NULL OR m.id > $4)
+            ORDER BY CASE WHEN $4::bigint IS NULL THEN -m.id ELSE m.id END
+            LIMIT $5
             "#,
         )
         .bind(chat_id as i64)
-        .bind(last_id as i64)
+        .bind(parent_id)
+        .bind(before)
+        .bind(after)
         .bind(limit)
         .fetch_all(&self.pool)
         .await?;
+
+        Ok(messages)
+    }
+
+    /// Edit a message, the previous content and files are kept as a revision.
+    pub async fn update_message(
+        &self,
+        input: UpdateMessage,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<Message, AppError> {
+        if input.content.is_none() && input.files.is_none() && input.format.is_none() {
+            return Err(AppError::UpdateMessageError(
+                "Content, files or format must be provided".to_string(),
+            ));
+        }
+        self.verify_chat_writable(chat_id, AppError::UpdateMessageError)
+            .await?;
+
+        let mut tx = self.pool.begin().await?;
+        let message: Option<Message> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, content, files, created_at, updated_at, deleted_at, parent_id, expires_at, quote, format, html, plain_text
+            FROM messages
+            WHERE id = $1 AND chat_id = $2
+            FOR UPDATE
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(message) = message else {
+            return Err(AppError::NotFound(format!("message id {message_id}")));
+        };
+
+        if message.sender_id != user_id as i64 {
+            return Err(AppError::PermissionDenied(
+                "Only the sender can edit the message".to_string(),
+            ));
+        }
+
+        if message.deleted_at.is_some() {
+            return Err(AppError::UpdateMessageError(
+                "Cannot edit a deleted message".to_string(),
+            ));
+        }
+
+        let content = input.content.unwrap_or_else(|| message.content.clone());
+        let files = input.files.unwrap_or_else(|| message.files.clone());
+        if content.is_empty() && files.is_empty() {
+            return Err(AppError::UpdateMessageError(
+                "Content or files must be provided".to_string(),
+            ));
+        }
+        self.verify_files(&files, AppError::UpdateMessageError)?;
+        let format = input.format.unwrap_or(message.format);
+        let rendered = render_content(&content, format, &files)?;
+        let mentions = self.resolve_mentions(chat_id, user_id, &content).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO message_revisions (message_id, content, files, created_at)
+            VALUES ($1, $2, $3, COALESCE($4, $5))
+            "#,
+        )
+        .bind(message.id)
+        .bind(&message.content)
+        .bind(&message.files)
+        .bind(message.updated_at)
+        .bind(message.created_at)
+        .execute(&mut *tx)
+        .await?;
+
+        let message: Message = sqlx::query_as(
+            r#"
+            UPDATE messages
+            SET content = $1, files = $2, format = $3, html = $4, plain_text = $5, updated_at = NOW()
+            WHERE id = $6
+            RETURNING id, chat_id, sender_id, content, files, created_at, updated_at, deleted_at, parent_id, expires_at, quote, format, html, plain_text
+            "#,
+        )
+        .bind(content)
+        .bind(&files)
+        .bind(format)
+        .bind(rendered.html)
+        .bind(rendered.plain_text)
+        .bind(message.id)
+        .fetch_one(&mut *tx)
+        .await?;
+        // users newly mentioned by the edit are notified, existing mentions are kept
+        self.create_mentions(&mut tx, &message, &mentions).await?;
+        tx.commit().await?;
+
+        Ok(message)
+    }
+
+    /// Soft delete a message, only the sender or a chat admin can do it.
+    /// The row is kept as a tombstone with its content, files and revisions cleared.
+    pub async fn delete_message(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<Message, AppError> {
+        self.verify_chat_writable(chat_id, AppError::UpdateMessageError)
+            .await?;
+        let mut tx = self.pool.begin().await?;
+        let message: Option<Message> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, content, files, created_at, updated_at, deleted_at, parent_id, expires_at, quote, format, html, plain_text
+            FROM messages
+            WHERE id = $1 AND chat_id = $2 AND deleted_at IS NULL
+            FOR UPDATE
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(message) = message else {
+            return Err(AppError::NotFound(format!("message id {message_id}")));
+        };
+
+        if message.sender_id != user_id as i64 && !self.is_chat_admin(chat_id, user_id).await? {
+            return Err(AppError::PermissionDenied(
+                "Only the sender or a chat admin can delete the message".to_string(),
+            ));
+        }
+
+        sqlx::query("DELETE FROM message_revisions WHERE message_id = $1")
+            .bind(message.id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM message_pins WHERE message_id = $1")
+            .bind(message.id)
+            .execute(&mut *tx)
+            .await?;
+
+        let message: Message = sqlx::query_as(
+            r#"
+            UPDATE messages
+            SET content = '', files = '{}', html = '', plain_text = '', deleted_at = NOW()
+            WHERE id = $1
+            RETURNING id, chat_id, sender_id, content, files, created_at, updated_at, deleted_at, parent_id, expires_at, quote, format, html, plain_text
+            "#,
+        )
+        .bind(message.id)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(message)
+    }
+
+    /// List previous revisions of a message, newest first.
+    pub async fn list_message_revisions(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+    ) -> Result<Vec<MessageRevision>, AppError> {
+        let exists = sqlx::query("SELECT 1 FROM messages WHERE id = $1 AND chat_id = $2")
+            .bind(message_id as i64)
+            .bind(chat_id as i64)
+            .fetch_optional(&self.pool)
+            .await?;
+        if exists.is_none() {
+            return Err(AppError::NotFound(format!("message id {message_id}")));
+        }
+
+        let revisions = sqlx::query_as(
+            r#"
+            SELECT id, message_id, content, files, created_at
+            FROM message_revisions
+            WHERE message_id = $1
+            ORDER BY id DESC
+            "#,
+        )
+        .bind(message_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(revisions)
+    }
+
+    /// Forward a message to other chats, the files are shared with the forwarded copies.
+    pub async fn forward_message(
+        &self,
+        input: ForwardMessage,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<Message>, AppError> {
+        let mut chat_ids = input.chat_ids;
+        chat_ids.sort_unstable();
+        chat_ids.dedup();
+        if chat_ids.is_empty() || chat_ids.len() > MAX_FORWARD_CHATS {
+            return Err(AppError::CreateMessageError(format!(
+                "Forward to 1 to {MAX_FORWARD_CHATS} chats"
+            )));
+        }
+        let source = self.get_quote(message_id, user_id).await?;
+        if source.chat_id != chat_id as i64 {
+            return Err(AppError::NotFound(format!("message id {message_id}")));
+        }
+        for id in &chat_ids {
+            if !self.is_chat_member(*id, user_id).await? {
+                return Err(AppError::PermissionDenied(format!(
+                    "Not a member of chat {id}"
+                )));
+            }
+        }
+
+        // all the copies are sent or none of them
+        let mut tx = self.pool.begin().await?;
+        let mut messages = vec![];
+        for id in chat_ids {
+            let input = CreateMessage {
+                content: input.comment.clone(),
+                files: source.files.clone(),
+                quote_of: Some(message_id),
+                ..Default::default()
+            };
+            messages.push(self.insert_message(&mut tx, input, id, user_id).await?);
+        }
+        tx.commit().await?;
         Ok(messages)
     }
+
+    /// Verify a new message, returns the root of the thread it replies to and the quote snapshot.
+    pub(crate) async fn verify_new_message(
+        &self,
+        input: &CreateMessage,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<(Option<i64>, Option<MessageQuote>), AppError> {
+        if input.content.is_empty()
+            && input.files.is_empty()
+            && input.quote_of.is_none()
+            && input.poll.is_none()
+        {
+            return Err(AppError::CreateMessageError(
+                "Content or files must be provided".to_string(),
+            ));
+        }
+        if self.is_chat_archived(chat_id).await? {
+            return Err(AppError::CreateMessageError(
+                "Cannot send messages to an archived chat".to_string(),
+            ));
+        }
+        self.verify_files(&input.files, AppError::CreateMessageError)?;
+        if input
+            .client_nonce
+            .as_ref()
+            .is_some_and(|nonce| nonce.is_empty() || nonce.len() > MAX_NONCE_LEN)
+        {
+            return Err(AppError::CreateMessageError(format!(
+                "client_nonce must be 1 to {MAX_NONCE_LEN} bytes"
+            )));
+        }
+        if let Some(poll) = &input.poll {
+            verify_poll(poll)?;
+        }
+        let parent_id = match input.parent_id {
+            Some(parent_id) => Some(self.get_thread_root(chat_id, parent_id).await?),
+            None => None,
+        };
+        let quote = match input.quote_of {
+            Some(quote_of) => Some(self.get_quote(quote_of, user_id).await?),
+            None => None,
+        };
+        Ok((parent_id, quote))
+    }
+
+    /// Snapshot a message for quoting, the user must be a member of its chat.
+    async fn get_quote(&self, message_id: u64, user_id: u64) -> Result<MessageQuote, AppError> {
+        let quote: Option<MessageQuote> = sqlx::query_as(
+            r#"
+            SELECT m.id AS message_id, m.chat_id, m.sender_id, u.fullname AS sender_name,
+                m.content, m.files, m.created_at
+            FROM messages m
+            JOIN users u ON u.id = m.sender_id
+            WHERE m.id = $1 AND m.deleted_at IS NULL
+            "#,
+        )
+        .bind(message_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(quote) = quote else {
+            return Err(AppError::NotFound(format!("message id {message_id}")));
+        };
+        if !self.is_chat_member(quote.chat_id as _, user_id).await? {
+            return Err(AppError::PermissionDenied(format!(
+                "Not a member of chat {}",
+                quote.chat_id
+            )));
+        }
+        Ok(quote)
+    }
+
+    /// Resolve the root of the thread a reply should be attached to.
+    async fn get_thread_root(&self, chat_id: u64, message_id: u64) -> Result<i64, AppError> {
+        let row: Option<(i64, Option<i64>, bool)> = sqlx::query_as(
+            r#"
+            SELECT id, parent_id, deleted_at IS NOT NULL
+            FROM messages
+            WHERE id = $1 AND chat_id = $2
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        match row {
+            None => Err(AppError::NotFound(format!("message id {message_id}"))),
+            Some((_, _, true)) => Err(AppError::CreateMessageError(
+                "Cannot reply to a deleted message".to_string(),
+            )),
+            Some((id, parent_id, false)) => Ok(parent_id.unwrap_or(id)),
+        }
+    }
+
+    pub(crate) fn verify_files(
+        &self,
+        files: &[String],
+        err: fn(String) -> AppError,
+    ) -> Result<(), AppError> {
+        let base_dir = &self.config.server.base_url;
+        for s in files {
+            let file = ChatFile::from_str(s)?;
+            if !file.path(base_dir).exists() {
+                return Err(err(format!("Invalid chat file path: {}", s)));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn encode_cursor(id: i64) -> String {
+    hex::encode(id.to_be_bytes())
+}
+
+fn decode_cursor(cursor: &str) -> Result<i64, AppError> {
+    hex::decode(cursor)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(i64::from_be_bytes)
+        .ok_or_else(|| AppError::CursorError(format!("Invalid cursor: {cursor}")))
 }
 
 #[cfg(test)]
@@ -98,7 +773,7 @@ mod tests {
         let (_tdb, state) = AppState::new_for_test().await?;
         let input = CreateMessage {
             content: "hello".to_string(),
-            files: vec![],
+            ..Default::default()
         };
         let message = state
             .create_message(input, 1, 1)
@@ -110,6 +785,7 @@ mod tests {
         let input = CreateMessage {
             content: "hello".to_string(),
             files: vec!["1".to_string()],
+            ..Default::default()
         };
         let err = state.create_message(input, 1, 1).await.unwrap_err();
         assert_eq!(err.to_string(), "Invalid chat file path: 1");
@@ -119,6 +795,7 @@ mod tests {
         let input = CreateMessage {
             content: "hello".to_string(),
             files: vec![url],
+            ..Default::default()
         };
         let message = state
             .create_message(input, 1, 1)
@@ -129,15 +806,132 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn long_message_should_fit_the_notification() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // the html and plain text renderings are left out of the notify payload
+        let input = CreateMessage {
+            content: "**long** ".repeat(500),
+            format: ContentFormat::Markdown,
+            ..Default::default()
+        };
+        let message = state.create_message(input, 1, 1).await?;
+        assert_eq!(message.content.len(), 4500);
+        assert!(!message.html.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_message_should_dedupe_by_nonce() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "once".to_string(),
+            client_nonce: Some("3f2a-retry".to_string()),
+            ..Default::default()
+        };
+        let message = state.create_message(input.clone(), 1, 1).await?;
+        assert_eq!(message.client_nonce.as_deref(), Some("3f2a-retry"));
+        let retried = state.create_message(input.clone(), 1, 1).await?;
+        assert_eq!(retried.id, message.id);
+
+        // the nonce is scoped to the sender and the chat
+        let other = state.create_message(input.clone(), 1, 2).await?;
+        assert_ne!(other.id, message.id);
+        let other = state.create_message(input.clone(), 2, 1).await?;
+        assert_ne!(other.id, message.id);
+
+        // retries after the window are new messages
+        sqlx::query("UPDATE messages SET created_at = NOW() - interval '2 days' WHERE id = $1")
+            .bind(message.id)
+            .execute(&state.pool)
+            .await?;
+        let late = state.create_message(input.clone(), 1, 1).await?;
+        assert_ne!(late.id, message.id);
+
+        let input = CreateMessage {
+            client_nonce: Some("".to_string()),
+            ..input
+        };
+        let err = state.create_message(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::CreateMessageError(_)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn archived_chat_should_reject_new_messages() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "anyone there?".to_string(),
+            ..Default::default()
+        };
+        state.archive_chat(1).await?;
+        let err = state.create_message(input.clone(), 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::CreateMessageError(_)));
+
+        // the history stays readable
+        let page = state.list_message(ListMessages::default(), 1, 1).await?;
+        assert_eq!(page.messages.len(), 10);
+
+        state.unarchive_chat(1).await?;
+        state.create_message(input, 1, 1).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn markdown_message_should_be_rendered() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let url = upload_dummy_file(&state)?;
+        let input = CreateMessage {
+            content: format!("**see** [the file]({url})"),
+            files: vec![url],
+            format: ContentFormat::Markdown,
+            ..Default::default()
+        };
+        let message = state.create_message(input, 1, 1).await?;
+        assert_eq!(message.format, ContentFormat::Markdown);
+        assert!(message
+            .html
+            .starts_with("<p><strong>see</strong> <a href=\"/files/"));
+        assert_eq!(message.plain_text, "see the file");
+
+        // dropping the file breaks the attachment reference
+        let input = UpdateMessage {
+            content: None,
+            files: Some(vec![]),
+            format: None,
+        };
+        let err = state
+            .update_message(input, 1, message.id as _, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::ContentError(_)));
+
+        let input = UpdateMessage {
+            content: None,
+            files: None,
+            format: Some(ContentFormat::Plain),
+        };
+        let message = state.update_message(input, 1, message.id as _, 1).await?;
+        assert!(message.html.starts_with("<p>**see** [the file]"));
+
+        let message = state.delete_message(1, message.id as _, 1).await?;
+        assert!(message.html.is_empty());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn list_messages_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let input = ListMessages {
-            last_id: None,
             limit: 6,
+            ..Default::default()
         };
 
-        let messages = state.list_message(input, 1).await?;
+        let messages = state.list_message(input, 1, 1).await?.messages;
         println!("{:#?}", messages);
         assert_eq!(messages.len(), 6);
 
@@ -146,14 +940,374 @@ mod tests {
         let input = ListMessages {
             last_id: Some(last_id as _),
             limit: 6,
+            ..Default::default()
         };
-        let messages = state.list_message(input, 1).await?;
+        let messages = state.list_message(input, 1, 1).await?.messages;
         println!("{:#?}", messages);
         assert_eq!(messages.len(), 4);
 
         Ok(())
     }
 
+    fn page_input(
+        before: Option<String>,
+        after: Option<String>,
+        around: Option<u64>,
+    ) -> ListMessages {
+        ListMessages {
+            last_id: None,
+            before,
+            after,
+            around,
+            limit: 4,
+        }
+    }
+
+    fn ids(page: &MessagePage) -> Vec<i64> {
+        page.messages.iter().map(|m| m.id).collect()
+    }
+
+    #[tokio::test]
+    async fn list_messages_should_page_both_ways() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let page = state
+            .list_message(page_input(None, None, None), 1, 1)
+            .await?;
+        assert_eq!(ids(&page), vec![10, 9, 8, 7]);
+        assert!(page.has_more_before);
+        assert!(!page.has_more_after);
+
+        let page = state
+            .list_message(page_input(page.before_cursor, None, None), 1, 1)
+            .await?;
+        assert_eq!(ids(&page), vec![6, 5, 4, 3]);
+        assert!(page.has_more_after);
+
+        let input = ListMessages {
+            limit: 2,
+            ..page_input(None, page.after_cursor, None)
+        };
+        let page = state.list_message(input, 1, 1).await?;
+        assert_eq!(ids(&page), vec![8, 7]);
+        assert!(page.has_more_after);
+
+        let page = state
+            .list_message(page_input(None, None, Some(5)), 1, 1)
+            .await?;
+        assert_eq!(ids(&page), vec![7, 6, 5, 4]);
+
+        // limit 0 no longer means the whole chat, but the fixture fits in a page
+        let input = ListMessages {
+            limit: 0,
+            ..page_input(None, None, None)
+        };
+        assert_eq!(state.list_message(input, 1, 1).await?.messages.len(), 10);
+
+        let input = page_input(Some("nope".to_string()), None, None);
+        let err = state.list_message(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::CursorError(_)));
+        let input = page_input(None, Some(encode_cursor(1)), Some(5));
+        let err = state.list_message(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::CursorError(_)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn get_message_context_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let context = state
+            .get_message_context(5, GetMessageContext { limit: 4 }, 2)
+            .await?;
+        assert_eq!(context.chat_id, 1);
+        assert_eq!(context.parent_id, None);
+        assert_eq!(ids(&context.page), vec![7, 6, 5, 4]);
+
+        let input = CreateMessage {
+            content: "in a thread".to_string(),
+            parent_id: Some(1),
+            ..Default::default()
+        };
+        let reply = state.create_message(input.clone(), 1, 1).await?;
+        let context = state
+            .get_message_context(reply.id as _, GetMessageContext { limit: 4 }, 2)
+            .await?;
+        assert_eq!(context.parent_id, Some(1));
+        assert_eq!(ids(&context.page), vec![reply.id]);
+
+        // daisy is not a member of chat 2
+        let input = CreateMessage {
+            content: "secret".to_string(),
+            parent_id: None,
+            ..input
+        };
+        let message = state.create_message(input, 2, 1).await?;
+        let err = state
+            .get_message_context(message.id as _, GetMessageContext { limit: 4 }, 5)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_message_should_keep_revisions() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = UpdateMessage {
+            content: Some("edited".to_string()),
+            files: None,
+            format: None,
+        };
+        let message = state.update_message(input, 1, 1, 1).await?;
+        assert_eq!(message.content, "edited");
+        assert!(message.updated_at.is_some());
+
+        let input = UpdateMessage {
+            content: Some("edited again".to_string()),
+            files: None,
+            format: None,
+        };
+        state.update_message(input, 1, 1, 1).await?;
+
+        let revisions = state.list_message_revisions(1, 1).await?;
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].content, "edited");
+        assert_eq!(revisions[1].content, "Hello, world!");
+
+        // only the sender can edit
+        let input = UpdateMessage {
+            content: Some("hacked".to_string()),
+            files: None,
+            format: None,
+        };
+        let err = state.update_message(input, 1, 1, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        // message must belong to the chat
+        let input = UpdateMessage {
+            content: Some("edited".to_string()),
+            files: None,
+            format: None,
+        };
+        let err = state.update_message(input, 2, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_message_should_leave_tombstone() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        // user 3 is neither the sender nor a chat admin
+        let err = state.delete_message(1, 1, 3).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        // the workspace owner administers the chat
+        state.update_workspace_owner(1, 3).await?;
+        let message = state.delete_message(1, 2, 3).await?;
+        assert!(message.deleted_at.is_some());
+
+        let input = UpdateMessage {
+            content: Some("edited".to_string()),
+            files: None,
+            format: None,
+        };
+        state.update_message(input, 1, 1, 1).await?;
+
+        let message = state.delete_message(1, 1, 1).await?;
+        assert!(message.deleted_at.is_some());
+        assert!(message.content.is_empty());
+
+        let revisions = state.list_message_revisions(1, 1).await?;
+        assert!(revisions.is_empty());
+
+        let input = ListMessages {
+            limit: 100,
+            ..Default::default()
+        };
+        let messages = state.list_message(input, 1, 1).await?.messages;
+        assert_eq!(messages.len(), 10);
+        let deleted: Vec<_> = messages.iter().filter(|m| m.deleted_at.is_some()).collect();
+        assert_eq!(deleted.len(), 2);
+
+        // deleting twice should fail
+        let err = state.delete_message(1, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn thread_replies_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        for content in ["first", "second"] {
+            let input = CreateMessage {
+                content: content.to_string(),
+                parent_id: Some(1),
+                ..Default::default()
+            };
+            let reply = state.create_message(input, 1, 2).await?;
+            assert_eq!(reply.parent_id, Some(1));
+        }
+
+        // replying to a reply attaches to the thread root
+        let input = CreateMessage {
+            content: "third".to_string(),
+            parent_id: Some(11),
+            ..Default::default()
+        };
+        let reply = state.create_message(input, 1, 3).await?;
+        assert_eq!(reply.parent_id, Some(1));
+
+        // replies stay out of the chat timeline
+        let input = ListMessages {
+            limit: 100,
+            ..Default::default()
+        };
+        let messages = state.list_message(input, 1, 1).await?.messages;
+        assert_eq!(messages.len(), 10);
+        let root = messages.last().expect("root should be listed");
+        assert_eq!(root.reply_count, 3);
+        assert!(root.last_reply_at.is_some());
+
+        let input = ListMessages {
+            limit: 2,
+            ..Default::default()
+        };
+        let replies = state.list_thread(input, 1, 1, 1).await?.messages;
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0].content, "third");
+
+        let input = ListMessages {
+            last_id: Some(replies[1].id as _),
+            limit: 2,
+            ..Default::default()
+        };
+        let replies = state.list_thread(input, 1, 1, 1).await?.messages;
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].content, "first");
+
+        // parent must belong to the same chat
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            parent_id: Some(1),
+            ..Default::default()
+        };
+        let err = state.create_message(input, 2, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn quote_should_keep_snapshot_of_original() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "".to_string(),
+            quote_of: Some(1),
+            ..Default::default()
+        };
+        let message = state.create_message(input, 3, 1).await?;
+        let quote = message.quote.expect("quote should exist");
+        assert_eq!(quote.sender_name, "Jim Wu");
+        assert_eq!(quote.content, "Hello, world!");
+
+        state.delete_message(1, 1, 1).await?;
+        let input = ListMessages {
+            limit: 1,
+            ..Default::default()
+        };
+        let messages = state.list_message(input, 3, 1).await?.messages;
+        let quote = messages[0].quote.as_ref().expect("quote should exist");
+        assert_eq!(quote.content, "Hello, world!");
+
+        // a deleted message can not be quoted anymore
+        let input = CreateMessage {
+            content: "again".to_string(),
+            quote_of: Some(1),
+            ..Default::default()
+        };
+        let err = state.create_message(input, 3, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        // user 4 is not a member of chat 3
+        let input = CreateMessage {
+            content: "peek".to_string(),
+            quote_of: Some(message.id as _),
+            ..Default::default()
+        };
+        let err = state.create_message(input, 1, 4).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forward_message_should_share_files() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let url = upload_dummy_file(&state)?;
+        let input = CreateMessage {
+            content: "with file".to_string(),
+            files: vec![url.clone()],
+            ..Default::default()
+        };
+        let source = state.create_message(input, 1, 1).await?;
+
+        let input = ForwardMessage {
+            chat_ids: vec![3, 4, 3],
+            comment: "fyi".to_string(),
+        };
+        let messages = state.forward_message(input, 1, source.id as _, 1).await?;
+        assert_eq!(messages.len(), 2);
+        for message in &messages {
+            assert_eq!(message.files, vec![url.clone()]);
+            assert_eq!(message.content, "fyi");
+            let quote = message.quote.as_ref().expect("quote should exist");
+            assert_eq!(quote.message_id, source.id);
+        }
+
+        // user 3 is not a member of chat 3
+        let input = ForwardMessage {
+            chat_ids: vec![3],
+            comment: "".to_string(),
+        };
+        let err = state
+            .forward_message(input, 1, source.id as _, 3)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        // the message does not belong to chat 2
+        let input = ForwardMessage {
+            chat_ids: vec![4],
+            comment: "".to_string(),
+        };
+        let err = state
+            .forward_message(input, 2, source.id as _, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        // chat 4 is archived, no copy is sent to chat 3 either
+        state.archive_chat(4).await?;
+        let sent = state.list_message(ListMessages::default(), 3, 1).await?;
+        let input = ForwardMessage {
+            chat_ids: vec![3, 4],
+            comment: "".to_string(),
+        };
+        let err = state
+            .forward_message(input, 1, source.id as _, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::CreateMessageError(_)));
+        let page = state.list_message(ListMessages::default(), 3, 1).await?;
+        assert_eq!(page.messages.len(), sent.messages.len());
+
+        Ok(())
+    }
+
     fn upload_dummy_file(state: &AppState) -> Result<String> {
         let file = ChatFile::new(1, "test.txt", b"hello world");
         let path = file.path(&state.config.server.base_url);
@@ -1,13 +1,41 @@
 use serde::{Deserialize, Serialize};
 
+mod channel;
 mod chat;
+mod content;
+mod draft;
+mod expiry;
 mod file;
+mod invite;
+mod member;
+mod mention;
 mod messages;
+mod pin;
+mod poll;
+mod reaction;
+mod read;
+mod saved;
+mod scheduled;
+mod search;
 mod user;
 mod workspace;
 
+pub use channel::{ChannelInfo, ListChannels};
 pub use chat::{CreateChat, UpdateChat};
-pub use messages::{CreateMessage, ListMessages};
+pub use draft::SaveDraft;
+pub use expiry::setup_expiry_sweeper;
+pub use invite::CreateInvite;
+pub use member::{ListMembers, UpdateMember};
+pub use mention::ListMentions;
+pub use messages::{
+    CreateMessage, ForwardMessage, GetMessageContext, ListMessages, MessageContext, MessagePage,
+    UpdateMessage,
+};
+pub use poll::{CreatePoll, VotePoll};
+pub use read::MarkRead;
+pub use saved::{setup_reminder_sweeper, ListSaved, SaveMessage};
+pub use scheduled::{setup_scheduled_dispatcher, RescheduleMessage};
+pub use search::{SearchHit, SearchMessages, SearchOutput};
 pub use user::{CreateUser, SigninUser};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
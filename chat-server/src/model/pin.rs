@@ -0,0 +1,185 @@
+use crate::{AppError, AppState};
+use chat_core::Pin;
+
+const MAX_PINS_PER_CHAT: i64 = 100;
+
+impl AppState {
+    /// Pin a message in its chat, pinning a pinned message is a no-op.
+    pub async fn pin_message(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<Pin, AppError> {
+        self.verify_chat_writable(chat_id, AppError::PinError)
+            .await?;
+        let mut tx = self.pool.begin().await?;
+        // serialize concurrent pins of the same chat so the limit holds
+        sqlx::query("SELECT 1 FROM chats WHERE id = $1 FOR UPDATE")
+            .bind(chat_id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        let message = sqlx::query(
+            "SELECT 1 FROM messages WHERE id = $1 AND chat_id = $2 AND deleted_at IS NULL",
+        )
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if message.is_none() {
+            return Err(AppError::NotFound(format!("message id {message_id}")));
+        }
+
+        let (pinned, count): (Option<bool>, i64) = sqlx::query_as(
+            r#"
+            SELECT BOOL_OR(message_id = $2), COUNT(*)
+            FROM message_pins
+            WHERE chat_id = $1
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(message_id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        if pinned != Some(true) && count >= MAX_PINS_PER_CHAT {
+            return Err(AppError::PinError(format!(
+                "A chat can have at most {MAX_PINS_PER_CHAT} pinned messages"
+            )));
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO message_pins (message_id, chat_id, pinned_by)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        self.fetch_pin(chat_id, message_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("message id {message_id}")))
+    }
+
+    /// Unpin a message, unpinning a message which is not pinned is a no-op.
+    pub async fn unpin_message(&self, chat_id: u64, message_id: u64) -> Result<(), AppError> {
+        self.verify_chat_writable(chat_id, AppError::PinError)
+            .await?;
+        sqlx::query("DELETE FROM message_pins WHERE message_id = $1 AND chat_id = $2")
+            .bind(message_id as i64)
+            .bind(chat_id as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// List the pinned messages of a chat, latest pinned first.
+    pub async fn list_pins(&self, chat_id: u64) -> Result<Vec<Pin>, AppError> {
+        let pins = sqlx::query_as(
+            r#"
+            SELECT p.pinned_by, p.created_at AS pinned_at,
+                m.id, m.chat_id, m.sender_id, m.content, m.files, m.created_at, m.updated_at,
+                m.deleted_at, m.parent_id, m.expires_at, m.quote, m.format, m.html, m.plain_text
+            FROM message_pins p
+            JOIN messages m ON m.id = p.message_id
+            WHERE p.chat_id = $1
+            ORDER BY p.created_at DESC, p.message_id DESC
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(pins)
+    }
+
+    async fn fetch_pin(&self, chat_id: u64, message_id: u64) -> Result<Option<Pin>, AppError> {
+        let pin = sqlx::query_as(
+            r#"
+            SELECT p.pinned_by, p.created_at AS pinned_at,
+                m.id, m.chat_id, m.sender_id, m.content, m.files, m.created_at, m.updated_at,
+                m.deleted_at, m.parent_id, m.expires_at, m.quote, m.format, m.html, m.plain_text
+            FROM message_pins p
+            JOIN messages m ON m.id = p.message_id
+            WHERE p.chat_id = $1 AND p.message_id = $2
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(message_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(pin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn pin_message_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let pin = state.pin_message(1, 1, 2).await?;
+        assert_eq!(pin.pinned_by, 2);
+        assert_eq!(pin.message.id, 1);
+        // pinning twice keeps the original pin
+        let pin = state.pin_message(1, 1, 3).await?;
+        assert_eq!(pin.pinned_by, 2);
+        state.pin_message(1, 2, 1).await?;
+
+        let pins = state.list_pins(1).await?;
+        assert_eq!(pins.len(), 2);
+        let chat = state.get_chat_by_id(1).await?.expect("chat should exist");
+        assert_eq!(chat.pin_count, 2);
+
+        // message 1 does not belong to chat 2
+        let err = state.pin_message(2, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        state.unpin_message(1, 2).await?;
+        assert_eq!(state.list_pins(1).await?.len(), 1);
+
+        // deleting the message removes its pin
+        state.delete_message(1, 1, 1).await?;
+        assert!(state.list_pins(1).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pin_message_should_respect_limit() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        sqlx::query(
+            r#"
+            WITH m AS (
+                INSERT INTO messages (chat_id, sender_id, content)
+                SELECT 1, 1, 'pin ' || i FROM generate_series(1, $1) i
+                RETURNING id
+            )
+            INSERT INTO message_pins (message_id, chat_id, pinned_by)
+            SELECT id, 1, 1 FROM m
+            "#,
+        )
+        .bind(MAX_PINS_PER_CHAT as i32)
+        .execute(&state.pool)
+        .await?;
+
+        let err = state.pin_message(1, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::PinError(_)));
+
+        // re-pinning a pinned message is still fine at the limit
+        let pins = state.list_pins(1).await?;
+        state.pin_message(1, pins[0].message.id as _, 1).await?;
+
+        Ok(())
+    }
+}
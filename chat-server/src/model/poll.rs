@@ -0,0 +1,388 @@
+use crate::{AppError, AppState};
+use chat_core::{Message, Poll, PollOption};
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgConnection};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+const MAX_POLL_OPTIONS: usize = 10;
+const MAX_POLL_OPTION_LEN: usize = 200;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreatePoll {
+    pub question: String,
+    pub options: Vec<String>,
+    #[serde(default)]
+    pub multiple: bool,
+    #[serde(default)]
+    pub anonymous: bool,
+    #[serde(default)]
+    pub closes_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct VotePoll {
+    /// indexes of the chosen options, empty to retract the vote
+    pub options: Vec<u32>,
+}
+
+#[derive(Debug, FromRow)]
+struct PollRow {
+    message_id: i64,
+    question: String,
+    options: Vec<String>,
+    multiple: bool,
+    anonymous: bool,
+    closes_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, FromRow)]
+struct TallyRow {
+    message_id: i64,
+    option_index: i32,
+    count: i64,
+    voters: Vec<i64>,
+    me: bool,
+}
+
+impl AppState {
+    pub(crate) async fn create_poll(
+        &self,
+        conn: &mut PgConnection,
+        message_id: i64,
+        input: &CreatePoll,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO polls (message_id, question, options, multiple, anonymous, closes_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(message_id)
+        .bind(&input.question)
+        .bind(&input.options)
+        .bind(input.multiple)
+        .bind(input.anonymous)
+        .bind(input.closes_at)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Replace the votes of the user on a poll.
+    pub async fn vote_poll(
+        &self,
+        input: VotePoll,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<Poll, AppError> {
+        self.verify_chat_writable(chat_id, AppError::PollError)
+            .await?;
+        let mut tx = self.pool.begin().await?;
+        // lock the poll so concurrent votes of the same user do not interleave
+        let poll: Option<PollRow> = sqlx::query_as(
+            r#"
+            SELECT p.message_id, p.question, p.options, p.multiple, p.anonymous, p.closes_at
+            FROM polls p
+            JOIN messages m ON m.id = p.message_id
+            WHERE p.message_id = $1 AND m.chat_id = $2 AND m.deleted_at IS NULL
+            FOR UPDATE OF p
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(poll) = poll else {
+            return Err(AppError::NotFound(format!("poll id {message_id}")));
+        };
+        if poll
+            .closes_at
+            .is_some_and(|closes_at| closes_at <= Utc::now())
+        {
+            return Err(AppError::PollError("The poll is closed".to_string()));
+        }
+
+        let mut options = input.options;
+        options.sort_unstable();
+        options.dedup();
+        if options.iter().any(|i| *i as usize >= poll.options.len()) {
+            return Err(AppError::PollError("Invalid poll option".to_string()));
+        }
+        if !poll.multiple && options.len() > 1 {
+            return Err(AppError::PollError(
+                "Only one option can be chosen".to_string(),
+            ));
+        }
+
+        sqlx::query("DELETE FROM poll_votes WHERE message_id = $1 AND user_id = $2")
+            .bind(message_id as i64)
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        let options: Vec<i32> = options.into_iter().map(|i| i as i32).collect();
+        sqlx::query(
+            r#"
+            INSERT INTO poll_votes (message_id, user_id, option_index)
+            SELECT $1, $2, unnest($3::integer[])
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .bind(&options)
+        .execute(&mut *tx)
+        .await?;
+        // the trigger on polls notifies the chat members with the new tallies
+        sqlx::query("UPDATE polls SET updated_at = NOW() WHERE message_id = $1")
+            .bind(message_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        let mut polls = self
+            .fetch_polls_by_message_ids(&[message_id as i64], user_id)
+            .await?;
+        polls
+            .remove(&(message_id as i64))
+            .ok_or_else(|| AppError::NotFound(format!("poll id {message_id}")))
+    }
+
+    /// Embed the polls with aggregated votes into the messages.
+    pub(crate) async fn attach_polls(
+        &self,
+        messages: &mut [Message],
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        let ids: Vec<i64> = messages.iter().map(|m| m.id).collect();
+        let mut polls = self.fetch_polls_by_message_ids(&ids, user_id).await?;
+        for message in messages {
+            message.poll = polls.remove(&message.id);
+        }
+        Ok(())
+    }
+
+    async fn fetch_polls_by_message_ids(
+        &self,
+        ids: &[i64],
+        user_id: u64,
+    ) -> Result<HashMap<i64, Poll>, AppError> {
+        let rows: Vec<PollRow> = sqlx::query_as(
+            r#"
+            SELECT message_id, question, options, multiple, anonymous, closes_at
+            FROM polls
+            WHERE message_id = ANY($1)
+            "#,
+        )
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await?;
+        if rows.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let ids: Vec<i64> = rows.iter().map(|row| row.message_id).collect();
+        let tallies: Vec<TallyRow> = sqlx::query_as(
+            r#"
+            SELECT message_id, option_index, COUNT(*) AS count,
+                array_agg(user_id ORDER BY created_at) AS voters, BOOL_OR(user_id = $2) AS me
+            FROM poll_votes
+            WHERE message_id = ANY($1)
+            GROUP BY message_id, option_index
+            "#,
+        )
+        .bind(&ids)
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        let voters: HashMap<i64, i64> = sqlx::query_as(
+            r#"
+            SELECT message_id, COUNT(DISTINCT user_id)
+            FROM poll_votes
+            WHERE message_id = ANY($1)
+            GROUP BY message_id
+            "#,
+        )
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .collect();
+
+        let mut polls: HashMap<i64, Poll> = rows
+            .into_iter()
+            .map(|row| {
+                let poll = Poll {
+                    question: row.question,
+                    options: row
+                        .options
+                        .into_iter()
+                        .map(|text| PollOption {
+                            text,
+                            count: 0,
+                            voters: vec![],
+                            me: false,
+                        })
+                        .collect(),
+                    multiple: row.multiple,
+                    anonymous: row.anonymous,
+                    closes_at: row.closes_at,
+                    voters: voters.get(&row.message_id).copied().unwrap_or_default(),
+                };
+                (row.message_id, poll)
+            })
+            .collect();
+        for tally in tallies {
+            let Some(poll) = polls.get_mut(&tally.message_id) else {
+                continue;
+            };
+            let anonymous = poll.anonymous;
+            if let Some(option) = poll.options.get_mut(tally.option_index as usize) {
+                option.count = tally.count;
+                option.me = tally.me;
+                if !anonymous {
+                    option.voters = tally.voters;
+                }
+            }
+        }
+        Ok(polls)
+    }
+}
+
+pub(crate) fn verify_poll(input: &CreatePoll) -> Result<(), AppError> {
+    if input.question.trim().is_empty() {
+        return Err(AppError::PollError(
+            "Poll question must be provided".to_string(),
+        ));
+    }
+    if input.options.len() < 2 || input.options.len() > MAX_POLL_OPTIONS {
+        return Err(AppError::PollError(format!(
+            "Poll must have 2 to {MAX_POLL_OPTIONS} options"
+        )));
+    }
+    if input
+        .options
+        .iter()
+        .any(|o| o.trim().is_empty() || o.chars().count() > MAX_POLL_OPTION_LEN)
+    {
+        return Err(AppError::PollError(format!(
+            "Poll option must be 1 to {MAX_POLL_OPTION_LEN} characters"
+        )));
+    }
+    if input
+        .closes_at
+        .is_some_and(|closes_at| closes_at <= Utc::now())
+    {
+        return Err(AppError::PollError(
+            "Poll must close in the future".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{CreateMessage, ListMessages};
+    use anyhow::Result;
+
+    fn poll_input(multiple: bool, anonymous: bool) -> CreateMessage {
+        CreateMessage {
+            poll: Some(CreatePoll {
+                question: "Lunch?".to_string(),
+                options: vec![
+                    "noodles".to_string(),
+                    "rice".to_string(),
+                    "salad".to_string(),
+                ],
+                multiple,
+                anonymous,
+                closes_at: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn vote(options: Vec<u32>) -> VotePoll {
+        VotePoll { options }
+    }
+
+    #[tokio::test]
+    async fn vote_poll_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let message = state.create_message(poll_input(false, false), 1, 1).await?;
+        assert_eq!(message.content, "Lunch?");
+        assert_eq!(message.poll.as_ref().map(|p| p.options.len()), Some(3));
+        let mid = message.id as u64;
+
+        state.vote_poll(vote(vec![0]), 1, mid, 2).await?;
+        let poll = state.vote_poll(vote(vec![1]), 1, mid, 3).await?;
+        assert_eq!(poll.voters, 2);
+        assert_eq!(poll.options[1].voters, vec![3]);
+        assert!(poll.options[1].me);
+        assert!(!poll.options[0].me);
+
+        // voting again replaces the previous vote
+        let poll = state.vote_poll(vote(vec![1]), 1, mid, 2).await?;
+        assert_eq!(poll.options[0].count, 0);
+        assert_eq!(poll.options[1].count, 2);
+
+        let err = state
+            .vote_poll(vote(vec![0, 1]), 1, mid, 2)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PollError(_)));
+        let err = state.vote_poll(vote(vec![3]), 1, mid, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::PollError(_)));
+        // the poll does not belong to chat 2
+        let err = state.vote_poll(vote(vec![0]), 2, mid, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        // an empty vote retracts it
+        let poll = state.vote_poll(vote(vec![]), 1, mid, 2).await?;
+        assert_eq!(poll.voters, 1);
+
+        let input = ListMessages {
+            limit: 1,
+            ..Default::default()
+        };
+        let messages = state.list_message(input, 1, 3).await?.messages;
+        let poll = messages[0].poll.as_ref().expect("poll should exist");
+        assert_eq!(poll.options[1].count, 1);
+        assert!(poll.options[1].me);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn vote_poll_should_respect_options() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let message = state.create_message(poll_input(true, true), 1, 1).await?;
+        let mid = message.id as u64;
+
+        // anonymous polls only expose the tallies
+        let poll = state.vote_poll(vote(vec![0, 2, 2]), 1, mid, 2).await?;
+        assert_eq!(poll.voters, 1);
+        assert_eq!(poll.options[0].count, 1);
+        assert_eq!(poll.options[2].count, 1);
+        assert!(poll.options[2].voters.is_empty());
+
+        sqlx::query("UPDATE polls SET closes_at = NOW() WHERE message_id = $1")
+            .bind(mid as i64)
+            .execute(&state.pool)
+            .await?;
+        let err = state.vote_poll(vote(vec![1]), 1, mid, 3).await.unwrap_err();
+        assert!(matches!(err, AppError::PollError(_)));
+
+        let mut input = poll_input(false, false);
+        if let Some(poll) = input.poll.as_mut() {
+            poll.options.truncate(1);
+        }
+        let err = state.create_message(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::PollError(_)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use crate::{AppError, AppState};
+use chat_core::{Message, Reaction};
+use sqlx::FromRow;
+use std::collections::HashMap;
+
+const MAX_EMOJI_LEN: usize = 64;
+
+#[derive(Debug, FromRow)]
+struct MessageReaction {
+    message_id: i64,
+    #[sqlx(flatten)]
+    reaction: Reaction,
+}
+
+impl AppState {
+    /// React to a message with an emoji, reacting twice with the same emoji is a no-op.
+    pub async fn add_reaction(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+        emoji: &str,
+    ) -> Result<Vec<Reaction>, AppError> {
+        verify_emoji(emoji)?;
+        self.verify_reaction_target(chat_id, message_id).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO message_reactions (message_id, user_id, emoji)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .bind(emoji)
+        .execute(&self.pool)
+        .await?;
+
+        self.fetch_reactions(message_id, user_id).await
+    }
+
+    /// Remove an emoji reaction of the user from a message.
+    pub async fn remove_reaction(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+        emoji: &str,
+    ) -> Result<Vec<Reaction>, AppError> {
+        self.verify_reaction_target(chat_id, message_id).await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM message_reactions
+            WHERE message_id = $1 AND user_id = $2 AND emoji = $3
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .bind(emoji)
+        .execute(&self.pool)
+        .await?;
+
+        self.fetch_reactions(message_id, user_id).await
+    }
+
+    /// Aggregated reactions of a message.
+    pub async fn fetch_reactions(
+        &self,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<Reaction>, AppError> {
+        let mut reactions = self
+            .fetch_reactions_by_message_ids(&[message_id as i64], user_id)
+            .await?;
+        Ok(reactions.remove(&(message_id as i64)).unwrap_or_default())
+    }
+
+    /// Embed aggregated reactions into the messages.
+    pub(crate) async fn attach_reactions(
+        &self,
+        messages: &mut [Message],
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        let ids: Vec<i64> = messages.iter().map(|m| m.id).collect();
+        let mut reactions = self.fetch_reactions_by_message_ids(&ids, user_id).await?;
+        for message in messages {
+            message.reactions = reactions.remove(&message.id).unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    async fn fetch_reactions_by_message_ids(
+        &self,
+        ids: &[i64],
+        user_id: u64,
+    ) -> Result<HashMap<i64, Vec<Reaction>>, AppError> {
+        let rows: Vec<MessageReaction> = sqlx::query_as(
+            r#"
+            SELECT message_id, emoji, COUNT(*) AS count, BOOL_OR(user_id = $2) AS me
+            FROM message_reactions
+            WHERE message_id = ANY($1)
+            GROUP BY message_id, emoji
+            ORDER BY MIN(created_at)
+            "#,
+        )
+        .bind(ids)
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut reactions: HashMap<i64, Vec<Reaction>> = HashMap::new();
+        for row in rows {
+            reactions
+                .entry(row.message_id)
+                .or_default()
+                .push(row.reaction);
+        }
+        Ok(reactions)
+    }
+
+    async fn verify_reaction_target(&self, chat_id: u64, message_id: u64) -> Result<(), AppError> {
+        self.verify_chat_writable(chat_id, AppError::ReactionError)
+            .await?;
+        let message = sqlx::query(
+            "SELECT 1 FROM messages WHERE id = $1 AND chat_id = $2 AND deleted_at IS NULL",
+        )
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        if message.is_none() {
+            return Err(AppError::NotFound(format!("message id {message_id}")));
+        }
+        Ok(())
+    }
+}
+
+fn verify_emoji(emoji: &str) -> Result<(), AppError> {
+    if emoji.is_empty() || emoji.chars().count() > MAX_EMOJI_LEN {
+        return Err(AppError::ReactionError(format!(
+            "Emoji must be 1 to {MAX_EMOJI_LEN} characters"
+        )));
+    }
+    if emoji.chars().any(char::is_whitespace) {
+        return Err(AppError::ReactionError(
+            "Emoji must not contain whitespace".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ListMessages;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn reactions_should_be_aggregated() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.add_reaction(1, 1, 1, "👍").await?;
+        state.add_reaction(1, 1, 1, "👍").await?;
+        state.add_reaction(1, 1, 2, "👍").await?;
+        let reactions = state.add_reaction(1, 1, 2, "🎉").await?;
+        assert_eq!(reactions.len(), 2);
+
+        let input = ListMessages {
+            last_id: Some(2),
+            limit: 1,
+            ..Default::default()
+        };
+        let messages = state.list_message(input, 1, 1).await?.messages;
+        let reactions = &messages[0].reactions;
+        assert_eq!(reactions[0].emoji, "👍");
+        assert_eq!(reactions[0].count, 2);
+        assert!(reactions[0].me);
+        assert_eq!(reactions[1].emoji, "🎉");
+        assert_eq!(reactions[1].count, 1);
+        assert!(!reactions[1].me);
+
+        let reactions = state.remove_reaction(1, 1, 2, "🎉").await?;
+        assert_eq!(reactions.len(), 1);
+
+        let err = state.add_reaction(1, 1, 1, "").await.unwrap_err();
+        assert!(matches!(err, AppError::ReactionError(_)));
+
+        // message 1 does not belong to chat 2
+        let err = state.add_reaction(2, 1, 1, "👍").await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        Ok(())
+    }
+}
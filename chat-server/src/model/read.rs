@@ -0,0 +1,163 @@
+use crate::{AppError, AppState};
+use chat_core::ReadReceipt;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct MarkRead {
+    /// the last message read, defaults to the latest message of the chat
+    #[serde(default)]
+    pub message_id: Option<u64>,
+}
+
+impl AppState {
+    /// Move the read position of the user forward, it never goes backwards.
+    pub async fn mark_read(
+        &self,
+        input: MarkRead,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<ReadReceipt, AppError> {
+        let message_id: Option<i64> = match input.message_id {
+            Some(id) => {
+                sqlx::query_scalar("SELECT id FROM messages WHERE id = $1 AND chat_id = $2")
+                    .bind(id as i64)
+                    .bind(chat_id as i64)
+                    .fetch_optional(&self.pool)
+                    .await?
+            }
+            None => {
+                sqlx::query_scalar("SELECT MAX(id) FROM messages WHERE chat_id = $1")
+                    .bind(chat_id as i64)
+                    .fetch_one(&self.pool)
+                    .await?
+            }
+        };
+        let message_id = match (message_id, input.message_id) {
+            (Some(message_id), _) => message_id,
+            (None, Some(id)) => return Err(AppError::NotFound(format!("message id {id}"))),
+            (None, None) => {
+                return Err(AppError::ReadError(format!(
+                    "chat id {chat_id} has no messages"
+                )))
+            }
+        };
+
+        let receipt = sqlx::query_as(
+            r#"
+            INSERT INTO chat_reads (chat_id, user_id, last_read_message_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (chat_id, user_id) DO UPDATE
+            SET last_read_message_id = EXCLUDED.last_read_message_id, updated_at = NOW()
+            WHERE chat_reads.last_read_message_id < EXCLUDED.last_read_message_id
+            RETURNING chat_id, user_id, last_read_message_id, updated_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(message_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some(receipt) = receipt {
+            return Ok(receipt);
+        }
+
+        let receipt = sqlx::query_as(
+            r#"
+            SELECT chat_id, user_id, last_read_message_id, updated_at
+            FROM chat_reads
+            WHERE chat_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(receipt)
+    }
+
+    /// List the members who have read the message.
+    pub async fn list_message_reads(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+    ) -> Result<Vec<ReadReceipt>, AppError> {
+        let receipts = sqlx::query_as(
+            r#"
+            SELECT r.chat_id, r.user_id, r.last_read_message_id, r.updated_at
+            FROM chat_reads r
+            JOIN chat_members cm ON cm.chat_id = r.chat_id AND cm.user_id = r.user_id
+            WHERE r.chat_id = $1 AND r.last_read_message_id >= $2
+            ORDER BY r.updated_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(message_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(receipts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn mark_read_should_update_unread_count() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let chats = state.fetch_chats(1, 1).await?;
+        let general = chats.iter().find(|c| c.id == 1).expect("chat 1");
+        // 6 of the 10 messages in chat 1 are sent by others
+        assert_eq!(general.unread_count, 6);
+        assert_eq!(general.last_read_id, None);
+
+        let input = MarkRead {
+            message_id: Some(5),
+        };
+        let receipt = state.mark_read(input, 1, 1).await?;
+        assert_eq!(receipt.last_read_message_id, 5);
+
+        // read position never goes backwards
+        let input = MarkRead {
+            message_id: Some(3),
+        };
+        let receipt = state.mark_read(input, 1, 1).await?;
+        assert_eq!(receipt.last_read_message_id, 5);
+
+        let chats = state.fetch_chats(1, 1).await?;
+        let general = chats.iter().find(|c| c.id == 1).expect("chat 1");
+        assert_eq!(general.unread_count, 2);
+        assert_eq!(general.last_read_id, Some(5));
+
+        state.mark_read(MarkRead::default(), 1, 2).await?;
+        let reads = state.list_message_reads(1, 4).await?;
+        assert_eq!(reads.len(), 2);
+        let reads = state.list_message_reads(1, 6).await?;
+        assert_eq!(reads.len(), 1);
+        assert_eq!(reads[0].user_id, 2);
+
+        // message 1 does not belong to chat 2
+        let input = MarkRead {
+            message_id: Some(1),
+        };
+        let err = state.mark_read(input, 2, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mark_read_empty_chat_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // chat 2 has no messages
+        let err = state
+            .mark_read(MarkRead::default(), 2, 1)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "read error: chat id 2 has no messages");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+use crate::{AppError, AppState};
+use chat_core::{ChatType, Message, SavedMessage};
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::FromRow;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{info, warn};
+use utoipa::{IntoParams, ToSchema};
+
+const REMIND_INTERVAL: Duration = Duration::from_secs(5);
+const REMIND_BATCH: i64 = 100;
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct SaveMessage {
+    #[serde(default)]
+    pub note: Option<String>,
+    /// when to remind the user about the message, delivered by notify-server
+    #[serde(default)]
+    pub remind_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct ListSaved {
+    #[serde(default)]
+    pub last_id: Option<u64>,
+    #[serde(default)]
+    pub limit: u64,
+}
+
+#[derive(Debug, FromRow)]
+struct SavedRow {
+    id: i64,
+    message_id: i64,
+    note: Option<String>,
+    remind_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    chat_name: Option<String>,
+    chat_type: ChatType,
+}
+
+impl AppState {
+    /// Save a message for later, saving it again updates the note and reminder.
+    pub async fn save_message(
+        &self,
+        input: SaveMessage,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<SavedMessage, AppError> {
+        let message = sqlx::query(
+            "SELECT 1 FROM messages WHERE id = $1 AND chat_id = $2 AND deleted_at IS NULL",
+        )
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        if message.is_none() {
+            return Err(AppError::NotFound(format!("message id {message_id}")));
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO saved_messages (user_id, message_id, note, remind_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, message_id)
+            DO UPDATE SET note = EXCLUDED.note, remind_at = EXCLUDED.remind_at,
+                reminded_at = CASE
+                    WHEN saved_messages.remind_at IS DISTINCT FROM EXCLUDED.remind_at THEN NULL
+                    ELSE saved_messages.reminded_at
+                END
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(message_id as i64)
+        .bind(input.note)
+        .bind(input.remind_at)
+        .execute(&self.pool)
+        .await?;
+
+        self.fetch_saved(user_id, Some(message_id), i64::MAX as _, 1)
+            .await?
+            .pop()
+            .ok_or_else(|| AppError::NotFound(format!("message id {message_id}")))
+    }
+
+    /// Remove a message from the saved list of the user.
+    pub async fn unsave_message(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            DELETE FROM saved_messages s
+            USING messages m
+            WHERE m.id = s.message_id AND s.user_id = $1 AND s.message_id = $2 AND m.chat_id = $3
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// List the saved messages of the user, latest saved first.
+    pub async fn list_saved(
+        &self,
+        input: ListSaved,
+        user_id: u64,
+    ) -> Result<Vec<SavedMessage>, AppError> {
+        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+        let limit = match input.limit {
+            0 => 20,
+            1..=100 => input.limit as _,
+            _ => 100,
+        };
+        self.fetch_saved(user_id, None, last_id, limit).await
+    }
+
+    /// Mark the due reminders as reminded, the trigger on saved_messages notifies the users.
+    /// Returns how many were due.
+    pub(crate) async fn remind_saved(&self) -> Result<u64, AppError> {
+        let ret = sqlx::query(
+            r#"
+            UPDATE saved_messages
+            SET reminded_at = NOW()
+            WHERE id IN (
+                SELECT id
+                FROM saved_messages
+                WHERE remind_at <= NOW() AND reminded_at IS NULL
+                ORDER BY remind_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            "#,
+        )
+        .bind(REMIND_BATCH)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(ret.rows_affected())
+    }
+
+    /// Entries of deleted messages or chats the user is no longer a member of are hidden.
+    async fn fetch_saved(
+        &self,
+        user_id: u64,
+        message_id: Option<u64>,
+        last_id: u64,
+        limit: i64,
+    ) -> Result<Vec<SavedMessage>, AppError> {
+        let rows: Vec<SavedRow> = sqlx::query_as(
+            r#"
+            SELECT s.id, s.message_id, s.note, s.remind_at, s.created_at,
+                c.name AS chat_name, c.type AS chat_type
+            FROM saved_messages s
+            JOIN messages m ON m.id = s.message_id
+            JOIN chats c ON c.id = m.chat_id
+            JOIN chat_members cm ON cm.chat_id = c.id AND cm.user_id = $1
+            WHERE s.user_id = $1 AND s.id < $2 AND ($3::bigint IS NULL OR s.message_id = $3)
+                AND m.deleted_at IS NULL
+            ORDER BY s.id DESC
+            LIMIT $4
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(last_id as i64)
+        .bind(message_id.map(|id| id as i64))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let ids: Vec<i64> = rows.iter().map(|row| row.message_id).collect();
+        let mut messages: Vec<Message> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, content, files, created_at, updated_at, deleted_at, parent_id, expires_at, quote, format, html, plain_text
+            FROM messages
+            WHERE id = ANY($1)
+            "#,
+        )
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await?;
+        self.attach_reactions(&mut messages, user_id).await?;
+        let mut messages: HashMap<i64, Message> = messages.into_iter().map(|m| (m.id, m)).collect();
+
+        let saved = rows
+            .into_iter()
+            .filter_map(|row| {
+                Some(SavedMessage {
+                    id: row.id,
+                    note: row.note,
+                    remind_at: row.remind_at,
+                    created_at: row.created_at,
+                    chat_name: row.chat_name,
+                    chat_type: row.chat_type,
+                    message: messages.remove(&row.message_id)?,
+                })
+            })
+            .collect();
+        Ok(saved)
+    }
+}
+
+/// Periodically remind the users of their saved messages which are due.
+pub fn setup_reminder_sweeper(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = interval(REMIND_INTERVAL);
+        loop {
+            ticker.tick().await;
+            match state.remind_saved().await {
+                Ok(0) => {}
+                Ok(reminded) => info!("Reminded {} saved messages", reminded),
+                Err(e) => warn!("Failed to remind saved messages: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::UpdateChat;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn saved_messages_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = SaveMessage {
+            note: Some("read later".to_string()),
+            remind_at: None,
+        };
+        let saved = state.save_message(input, 1, 1, 3).await?;
+        assert_eq!(saved.note.as_deref(), Some("read later"));
+        assert_eq!(saved.chat_name.as_deref(), Some("general"));
+        assert_eq!(saved.message.id, 1);
+
+        // saving again updates the note
+        let saved = state.save_message(SaveMessage::default(), 1, 1, 3).await?;
+        assert_eq!(saved.note, None);
+        state.save_message(SaveMessage::default(), 1, 2, 3).await?;
+        state.save_message(SaveMessage::default(), 1, 3, 3).await?;
+
+        let input = ListSaved {
+            last_id: None,
+            limit: 2,
+        };
+        let saved = state.list_saved(input, 3).await?;
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved[0].message.id, 3);
+        let input = ListSaved {
+            last_id: Some(saved[1].id as _),
+            limit: 2,
+        };
+        let saved = state.list_saved(input, 3).await?;
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].message.id, 1);
+
+        state.unsave_message(1, 2, 3).await?;
+        let input = ListSaved {
+            last_id: None,
+            limit: 0,
+        };
+        assert_eq!(state.list_saved(input.clone(), 3).await?.len(), 2);
+
+        // leaving the chat hides the saved messages
+        let update = UpdateChat {
+            name: None,
+            members: Some(vec![1, 2, 4, 5]),
+            chat_type: None,
+            message_ttl: None,
+        };
+        state.update_chat_by_id(1, update, 1).await?;
+        assert!(state.list_saved(input, 3).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn saved_messages_should_be_reminded_once() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let remind_at = Utc::now();
+        let input = SaveMessage {
+            note: None,
+            remind_at: Some(remind_at),
+        };
+        state.save_message(input.clone(), 1, 1, 3).await?;
+        state.save_message(SaveMessage::default(), 1, 2, 3).await?;
+        assert_eq!(state.remind_saved().await?, 1);
+        assert_eq!(state.remind_saved().await?, 0);
+
+        // saving again only reminds again with a new remind_at
+        state.save_message(input, 1, 1, 3).await?;
+        assert_eq!(state.remind_saved().await?, 0);
+        let input = SaveMessage {
+            note: None,
+            remind_at: Some(remind_at - Duration::from_secs(60)),
+        };
+        state.save_message(input, 1, 1, 3).await?;
+        assert_eq!(state.remind_saved().await?, 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,373 @@
+use super::content::render_content;
+use super::CreateMessage;
+use crate::{AppError, AppState};
+use chat_core::{Message, ScheduledMessage};
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{Acquire, PgConnection};
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+const DISPATCH_INTERVAL: Duration = Duration::from_secs(1);
+const DISPATCH_BATCH: usize = 100;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct RescheduleMessage {
+    pub send_at: DateTime<Utc>,
+}
+
+impl AppState {
+    /// Store a message to be delivered at `send_at`, a past `send_at` is delivered right away.
+    /// Retrying with the `client_nonce` of a pending message returns that message.
+    pub async fn schedule_message(
+        &self,
+        input: CreateMessage,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<ScheduledMessage, AppError> {
+        let Some(send_at) = input.send_at else {
+            return Err(AppError::CreateMessageError(
+                "send_at must be provided".to_string(),
+            ));
+        };
+        if input.poll.is_some() {
+            return Err(AppError::CreateMessageError(
+                "Polls can not be scheduled".to_string(),
+            ));
+        }
+        let (parent_id, _) = self.verify_new_message(&input, chat_id, user_id).await?;
+        render_content(&input.content, input.format, &input.files)?;
+
+        let mut tx = self.pool.begin().await?;
+        if let Some(nonce) = &input.client_nonce {
+            // serialize the retries of the same schedule, the first one wins
+            sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))")
+                .bind(format!("scheduled:{chat_id}:{user_id}:{nonce}"))
+                .execute(&mut *tx)
+                .await?;
+            let pending: Option<ScheduledMessage> = sqlx::query_as(
+                r#"
+                SELECT id, chat_id, sender_id, content, format, files, parent_id, quote_of, send_at, created_at,
+                    failed_at, error, client_nonce
+                FROM scheduled_messages
+                WHERE chat_id = $1 AND sender_id = $2 AND client_nonce = $3
+                "#,
+            )
+            .bind(chat_id as i64)
+            .bind(user_id as i64)
+            .bind(nonce)
+            .fetch_optional(&mut *tx)
+            .await?;
+            if let Some(pending) = pending {
+                tx.commit().await?;
+                return Ok(pending);
+            }
+        }
+        let scheduled = sqlx::query_as(
+            r#"
+            INSERT INTO scheduled_messages (chat_id, sender_id, content, format, files, parent_id, quote_of, send_at,
+                client_nonce)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING id, chat_id, sender_id, content, format, files, parent_id, quote_of, send_at, created_at,
+                failed_at, error, client_nonce
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(input.content)
+        .bind(input.format)
+        .bind(&input.files)
+        .bind(parent_id)
+        .bind(input.quote_of.map(|id| id as i64))
+        .bind(send_at)
+        .bind(&input.client_nonce)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(scheduled)
+    }
+
+    /// List the pending messages of the user in the chat, the next to be sent first.
+    pub async fn list_scheduled(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<ScheduledMessage>, AppError> {
+        let scheduled = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, content, format, files, parent_id, quote_of, send_at, created_at,
+                failed_at, error, client_nonce
+            FROM scheduled_messages
+            WHERE chat_id = $1 AND sender_id = $2
+            ORDER BY send_at, id
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(scheduled)
+    }
+
+    pub async fn reschedule_message(
+        &self,
+        input: RescheduleMessage,
+        chat_id: u64,
+        id: u64,
+        user_id: u64,
+    ) -> Result<ScheduledMessage, AppError> {
+        let scheduled = sqlx::query_as(
+            r#"
+            UPDATE scheduled_messages
+            SET send_at = $4, failed_at = NULL, error = NULL
+            WHERE id = $1 AND chat_id = $2 AND sender_id = $3
+            RETURNING id, chat_id, sender_id, content, format, files, parent_id, quote_of, send_at, created_at,
+                failed_at, error, client_nonce
+            "#,
+        )
+        .bind(id as i64)
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(input.send_at)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        scheduled.ok_or_else(|| AppError::NotFound(format!("scheduled message id {id}")))
+    }
+
+    pub async fn cancel_scheduled(
+        &self,
+        chat_id: u64,
+        id: u64,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        let result = sqlx::query(
+            "DELETE FROM scheduled_messages WHERE id = $1 AND chat_id = $2 AND sender_id = $3",
+        )
+        .bind(id as i64)
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("scheduled message id {id}")));
+        }
+
+        Ok(())
+    }
+
+    /// Send the due messages one by one, the next to be sent first, returns how many were sent.
+    pub(crate) async fn dispatch_scheduled(&self) -> usize {
+        let mut sent = 0;
+        for _ in 0..DISPATCH_BATCH {
+            match self.dispatch_next().await {
+                Ok(Some(true)) => sent += 1,
+                Ok(Some(false)) => {}
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Failed to dispatch scheduled messages: {}", e);
+                    break;
+                }
+            }
+        }
+        sent
+    }
+
+    /// Claim the next due message and send it in the same transaction, so it is never lost
+    /// or delivered twice. A message which can not be sent is kept and marked as failed.
+    /// Returns None when no message is due.
+    async fn dispatch_next(&self) -> Result<Option<bool>, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let scheduled: Option<ScheduledMessage> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, content, format, files, parent_id, quote_of, send_at, created_at,
+                failed_at, error, client_nonce
+            FROM scheduled_messages
+            WHERE send_at <= NOW() AND failed_at IS NULL
+            ORDER BY send_at, id
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+            "#,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(scheduled) = scheduled else {
+            return Ok(None);
+        };
+
+        // a failed send only rolls back to the savepoint, the claim is kept
+        let mut savepoint = tx.begin().await?;
+        let sent = match self.send_scheduled(&mut savepoint, &scheduled).await {
+            Ok(_) => {
+                savepoint.commit().await?;
+                sqlx::query("DELETE FROM scheduled_messages WHERE id = $1")
+                    .bind(scheduled.id)
+                    .execute(&mut *tx)
+                    .await?;
+                true
+            }
+            Err(e) => {
+                savepoint.rollback().await?;
+                warn!("Failed to send scheduled message {}: {}", scheduled.id, e);
+                sqlx::query(
+                    "UPDATE scheduled_messages SET failed_at = NOW(), error = $2 WHERE id = $1",
+                )
+                .bind(scheduled.id)
+                .bind(e.to_string())
+                .execute(&mut *tx)
+                .await?;
+                false
+            }
+        };
+        tx.commit().await?;
+
+        Ok(Some(sent))
+    }
+
+    async fn send_scheduled(
+        &self,
+        conn: &mut PgConnection,
+        scheduled: &ScheduledMessage,
+    ) -> Result<Message, AppError> {
+        let (chat_id, sender_id) = (scheduled.chat_id as u64, scheduled.sender_id as u64);
+        if !self.is_chat_member(chat_id, sender_id).await? {
+            return Err(AppError::CreateMessageError(
+                "The sender is not a member of the chat".to_string(),
+            ));
+        }
+        let input = CreateMessage {
+            content: scheduled.content.clone(),
+            files: scheduled.files.clone(),
+            parent_id: scheduled.parent_id.map(|id| id as u64),
+            quote_of: scheduled.quote_of.map(|id| id as u64),
+            format: scheduled.format,
+            client_nonce: scheduled.client_nonce.clone(),
+            ..Default::default()
+        };
+        self.insert_message(conn, input, chat_id, sender_id).await
+    }
+}
+
+/// Periodically send the scheduled messages which are due.
+pub fn setup_scheduled_dispatcher(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = interval(DISPATCH_INTERVAL);
+        loop {
+            ticker.tick().await;
+            match state.dispatch_scheduled().await {
+                0 => {}
+                sent => info!("Sent {} scheduled messages", sent),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ListMessages, UpdateChat};
+    use anyhow::Result;
+
+    fn scheduled_input(content: &str, send_at: DateTime<Utc>) -> CreateMessage {
+        CreateMessage {
+            content: content.to_string(),
+            send_at: Some(send_at),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn scheduled_messages_should_be_dispatched_when_due() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let later = Utc::now() + Duration::from_secs(3600);
+        let first = state
+            .schedule_message(scheduled_input("first", later), 1, 1)
+            .await?;
+        let second = state
+            .schedule_message(scheduled_input("second", later), 1, 1)
+            .await?;
+        let left = state
+            .schedule_message(scheduled_input("left", later), 1, 3)
+            .await?;
+        assert_eq!(state.list_scheduled(1, 1).await?.len(), 2);
+        assert_eq!(state.dispatch_scheduled().await, 0);
+
+        // only the sender can change a scheduled message
+        let input = RescheduleMessage {
+            send_at: Utc::now(),
+        };
+        let err = state
+            .reschedule_message(input.clone(), 1, first.id as _, 2)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        state
+            .reschedule_message(input.clone(), 1, first.id as _, 1)
+            .await?;
+        state.reschedule_message(input, 1, left.id as _, 3).await?;
+        state.cancel_scheduled(1, second.id as _, 1).await?;
+        assert_eq!(state.list_scheduled(1, 1).await?.len(), 1);
+
+        // user 3 left the chat before the message is due
+        let update = UpdateChat {
+            name: None,
+            members: Some(vec![1, 2, 4, 5]),
+            chat_type: None,
+            message_ttl: None,
+        };
+        state.update_chat_by_id(1, update, 1).await?;
+
+        assert_eq!(state.dispatch_scheduled().await, 1);
+        assert_eq!(state.dispatch_scheduled().await, 0);
+        // the message of user 3 is kept and marked as failed
+        let failed = state.list_scheduled(1, 3).await?;
+        assert_eq!(failed.len(), 1);
+        assert!(failed[0].failed_at.is_some());
+        assert!(failed[0].error.is_some());
+        let input = ListMessages {
+            limit: 1,
+            ..Default::default()
+        };
+        let messages = state.list_message(input, 1, 1).await?.messages;
+        assert_eq!(messages[0].content, "first");
+
+        let err = state
+            .schedule_message(scheduled_input("", later), 1, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::CreateMessageError(_)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scheduled_messages_should_dedupe_by_nonce() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            client_nonce: Some("7c1e-retry".to_string()),
+            ..scheduled_input("once", Utc::now())
+        };
+        let scheduled = state.schedule_message(input.clone(), 1, 1).await?;
+        let retried = state.schedule_message(input.clone(), 1, 1).await?;
+        assert_eq!(retried.id, scheduled.id);
+        assert_eq!(state.list_scheduled(1, 1).await?.len(), 1);
+
+        // the nonce is passed on, so a retry after the dispatch is not sent twice
+        assert_eq!(state.dispatch_scheduled().await, 1);
+        state.schedule_message(input, 1, 1).await?;
+        assert_eq!(state.dispatch_scheduled().await, 1);
+        let input = ListMessages {
+            limit: 2,
+            ..Default::default()
+        };
+        let messages = state.list_message(input, 1, 1).await?.messages;
+        assert_eq!(messages[0].content, "once");
+        assert_ne!(messages[1].content, "once");
+
+        Ok(())
+    }
+}
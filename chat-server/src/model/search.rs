@@ -0,0 +1,340 @@
+use crate::{AppError, AppState};
+use chat_core::Message;
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::NaiveDate;
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+
+/// Control characters standing in for the marks and the html special characters while the
+/// content is highlighted, so that a mark never lands inside an escaped entity.
+const MARK_START: char = '\u{2}';
+const MARK_STOP: char = '\u{3}';
+const LT: char = '\u{4}';
+const GT: char = '\u{5}';
+const AMP: char = '\u{6}';
+
+#[derive(Debug, Clone, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct SearchMessages {
+    /// search text with optional `from:`, `in:`, `has:file`, `before:` and `after:` operators
+    pub q: String,
+    #[serde(default)]
+    pub cursor: Option<u64>,
+    #[serde(default)]
+    pub limit: u64,
+}
+
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize)]
+pub struct SearchHit {
+    #[sqlx(flatten)]
+    pub message: Message,
+    /// html escaped content with the matches wrapped in `<mark>`
+    pub snippet: String,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct SearchOutput {
+    pub hits: Vec<SearchHit>,
+    pub next_cursor: Option<u64>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct SearchQuery {
+    text: String,
+    from: Option<String>,
+    chat: Option<String>,
+    has_file: bool,
+    before: Option<NaiveDate>,
+    after: Option<NaiveDate>,
+}
+
+impl AppState {
+    /// Search messages in the chats of the user, newest first.
+    pub async fn search_messages(
+        &self,
+        input: SearchMessages,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<SearchOutput, AppError> {
+        let query = parse_search_query(&input.q)?;
+        let sender_id = match &query.from {
+            Some(from) => Some(self.resolve_search_user(from, ws_id).await?),
+            None => None,
+        };
+        let chat_id = match &query.chat {
+            Some(chat) => Some(self.resolve_search_chat(chat, user_id, ws_id).await?),
+            None => None,
+        };
+        let cursor = input.cursor.unwrap_or(i64::MAX as _);
+        let limit = match input.limit {
+            0 => 20,
+            1..=100 => input.limit as i64,
+            _ => 100,
+        };
+
+        // highlight the content before it is escaped, see `render_snippet`
+        let mut hits: Vec<SearchHit> = sqlx::query_as(
+            r#"
+            SELECT m.id, m.chat_id, m.sender_id, m.content, m.files, m.created_at, m.updated_at,
+                m.deleted_at, m.parent_id, m.expires_at, m.quote, m.format, m.html, m.plain_text,
+                CASE WHEN $3 = '' THEN e.content
+                    ELSE ts_headline('simple', e.content, websearch_to_tsquery('simple', $3),
+                        format('StartSel=%s, StopSel=%s', chr(2), chr(3)))
+                END AS snippet
+            FROM messages m
+            JOIN chats c ON c.id = m.chat_id
+            JOIN chat_members cm ON cm.chat_id = c.id AND cm.user_id = $2
+            CROSS JOIN LATERAL (
+                SELECT translate(translate(m.content, chr(2) || chr(3) || chr(4) || chr(5) || chr(6), ''),
+                    '<>&', chr(4) || chr(5) || chr(6)) AS content
+            ) e
+            WHERE c.ws_id = $1 AND m.deleted_at IS NULL
+                AND ($3 = '' OR to_tsvector('simple', m.content) @@ websearch_to_tsquery('simple', $3))
+                AND ($4::bigint IS NULL OR m.sender_id = $4)
+                AND ($5::bigint IS NULL OR m.chat_id = $5)
+                AND (NOT $6 OR cardinality(m.files) > 0)
+                AND ($7::date IS NULL OR m.created_at < $7)
+                AND ($8::date IS NULL OR m.created_at >= $8 + 1)
+                AND m.id < $9
+            ORDER BY m.id DESC
+            LIMIT $10
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(&query.text)
+        .bind(sender_id)
+        .bind(chat_id)
+        .bind(query.has_file)
+        .bind(query.before)
+        .bind(query.after)
+        .bind(cursor as i64)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        for hit in &mut hits {
+            hit.snippet = render_snippet(&hit.snippet);
+        }
+
+        let next_cursor = match hits.last() {
+            Some(hit) if hits.len() as i64 == limit => Some(hit.message.id as u64),
+            _ => None,
+        };
+        Ok(SearchOutput { hits, next_cursor })
+    }
+
+    /// `from:` accepts a user id or the local part of the email.
+    async fn resolve_search_user(&self, from: &str, ws_id: u64) -> Result<i64, AppError> {
+        let from = from.trim_start_matches('@');
+        if let Ok(id) = from.parse() {
+            return Ok(id);
+        }
+        self.fetch_chat_users(ws_id)
+            .await?
+            .into_iter()
+            .find(|user| {
+                user.email
+                    .split_once('@')
+                    .is_some_and(|(name, _)| name.eq_ignore_ascii_case(from))
+            })
+            .map(|user| user.id)
+            .ok_or_else(|| AppError::SearchError(format!("Unknown user: {from}")))
+    }
+
+    /// `in:` accepts a chat id or the name of a chat of the user, archived ones included.
+    async fn resolve_search_chat(
+        &self,
+        chat: &str,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<i64, AppError> {
+        let chat = chat.trim_start_matches('#');
+        if let Ok(id) = chat.parse() {
+            return Ok(id);
+        }
+        let id: Option<i64> = sqlx::query_scalar(
+            r#"
+            SELECT c.id
+            FROM chats c
+            JOIN chat_members cm ON cm.chat_id = c.id AND cm.user_id = $2
+            WHERE c.ws_id = $1 AND lower(c.name) = lower($3)
+            ORDER BY c.id
+            LIMIT 1
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(chat)
+        .fetch_optional(&self.pool)
+        .await?;
+        id.ok_or_else(|| AppError::SearchError(format!("Unknown chat: {chat}")))
+    }
+}
+
+fn parse_search_query(q: &str) -> Result<SearchQuery, AppError> {
+    let mut query = SearchQuery::default();
+    let mut text = vec![];
+    for word in q.split_whitespace() {
+        let Some((op, value)) = word.split_once(':') else {
+            text.push(word);
+            continue;
+        };
+        match op {
+            "from" => query.from = Some(value.to_string()),
+            "in" => query.chat = Some(value.to_string()),
+            "has" if value == "file" => query.has_file = true,
+            "has" => {
+                return Err(AppError::SearchError(format!(
+                    "Unsupported operator: has:{value}"
+                )))
+            }
+            "before" => query.before = Some(parse_date(value)?),
+            "after" => query.after = Some(parse_date(value)?),
+            _ => text.push(word),
+        }
+    }
+    query.text = text.join(" ");
+    Ok(query)
+}
+
+/// Escape the highlighted content as html and wrap the matches in `<mark>`.
+fn render_snippet(highlighted: &str) -> String {
+    let mut snippet = String::with_capacity(highlighted.len());
+    for c in highlighted.chars() {
+        match c {
+            '&' | AMP => snippet.push_str("&amp;"),
+            '<' | LT => snippet.push_str("&lt;"),
+            '>' | GT => snippet.push_str("&gt;"),
+            MARK_START => snippet.push_str("<mark>"),
+            MARK_STOP => snippet.push_str("</mark>"),
+            c => snippet.push(c),
+        }
+    }
+    snippet
+}
+
+fn parse_date(value: &str) -> Result<NaiveDate, AppError> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| AppError::SearchError(format!("Invalid date: {value}, expect YYYY-MM-DD")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::CreateMessage;
+    use anyhow::Result;
+
+    #[test]
+    fn parse_search_query_should_work() -> Result<()> {
+        let query = parse_search_query("hello from:alice in:#general has:file after:2025-01-02")?;
+        assert_eq!(
+            query,
+            SearchQuery {
+                text: "hello".to_string(),
+                from: Some("alice".to_string()),
+                chat: Some("#general".to_string()),
+                has_file: true,
+                before: None,
+                after: NaiveDate::from_ymd_opt(2025, 1, 2),
+            }
+        );
+        assert!(parse_search_query("before:yesterday").is_err());
+        assert!(parse_search_query("has:link").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn render_snippet_should_escape_around_the_marks() {
+        let highlighted = format!("a{LT}b {AMP} {MARK_START}c{MARK_STOP}>");
+        assert_eq!(
+            render_snippet(&highlighted),
+            "a&lt;b &amp; <mark>c</mark>&gt;"
+        );
+    }
+
+    #[tokio::test]
+    async fn search_messages_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = SearchMessages {
+            q: "hello".to_string(),
+            cursor: None,
+            limit: 3,
+        };
+        let output = state.search_messages(input, 1, 1).await?;
+        assert_eq!(output.hits.len(), 3);
+        assert_eq!(output.hits[0].snippet, "<mark>Hello</mark>, world!");
+        let cursor = output.next_cursor.expect("next cursor should exist");
+
+        let input = SearchMessages {
+            q: "hello".to_string(),
+            cursor: Some(cursor),
+            limit: 3,
+        };
+        let output = state.search_messages(input, 1, 1).await?;
+        assert_eq!(output.hits.len(), 1);
+        assert_eq!(output.next_cursor, None);
+
+        let input = SearchMessages {
+            q: "from:alice in:general".to_string(),
+            cursor: None,
+            limit: 10,
+        };
+        let output = state.search_messages(input, 1, 1).await?;
+        assert_eq!(output.hits.len(), 2);
+
+        // daisy is not a member of chat 2
+        let message = CreateMessage {
+            content: "secret <b>plan</b>".to_string(),
+            ..Default::default()
+        };
+        state.create_message(message, 2, 1).await?;
+        let input = SearchMessages {
+            q: "plan".to_string(),
+            cursor: None,
+            limit: 10,
+        };
+        let output = state.search_messages(input.clone(), 5, 1).await?;
+        assert!(output.hits.is_empty());
+        let output = state.search_messages(input, 1, 1).await?;
+        assert_eq!(
+            output.hits[0].snippet,
+            "secret &lt;b&gt;<mark>plan</mark>&lt;/b&gt;"
+        );
+
+        // the escaped entities are not matched
+        let message = CreateMessage {
+            content: "1<lt & amp".to_string(),
+            ..Default::default()
+        };
+        state.create_message(message, 2, 1).await?;
+        for (q, snippet) in [
+            ("lt", "1&lt;<mark>lt</mark> &amp; amp"),
+            ("amp", "1&lt;lt &amp; <mark>amp</mark>"),
+        ] {
+            let input = SearchMessages {
+                q: q.to_string(),
+                cursor: None,
+                limit: 10,
+            };
+            let output = state.search_messages(input, 1, 1).await?;
+            assert_eq!(output.hits.len(), 1);
+            assert_eq!(output.hits[0].snippet, snippet);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_in_archived_chat_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.archive_chat(1).await?;
+        let input = SearchMessages {
+            q: "in:general".to_string(),
+            cursor: None,
+            limit: 10,
+        };
+        let output = state.search_messages(input, 1, 1).await?;
+        assert_eq!(output.hits.len(), 10);
+
+        Ok(())
+    }
+}
@@ -1,10 +1,17 @@
 use crate::handler::*;
 use crate::{
-    AppState, AuthOutput, CreateChat, CreateMessage, CreateUser, ErrorOutput, ListMessages,
-    SigninUser,
+    AppState, AuthOutput, ChannelInfo, CreateChat, CreateInvite, CreateMessage, CreatePoll,
+    CreateUser, ErrorOutput, ForwardMessage, GetMessageContext, ListChannels, ListMembers,
+    ListMentions, ListMessages, ListSaved, MarkRead, MessageContext, MessagePage,
+    RescheduleMessage, SaveDraft, SaveMessage, SearchHit, SearchMessages, SearchOutput, SigninUser,
+    UpdateMember, UpdateMessage, VotePoll,
 };
 use axum::Router;
-use chat_core::{Chat, ChatType, ChatUser, Message, User, Workspace};
+use chat_core::{
+    Chat, ChatInvite, ChatMember, ChatRole, ChatType, ChatUser, ContentFormat, Draft, Mention,
+    MentionKind, Message, MessageQuote, MessageRevision, Pin, Poll, PollOption, Reaction,
+    ReadReceipt, SavedMessage, ScheduledMessage, User, Workspace,
+};
 use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
 use utoipa::{Modify, OpenApi};
 use utoipa_rapidoc::RapiDoc;
@@ -23,16 +30,55 @@ pub(crate) trait OpenApiRouter {
         get_chat_handler,
         update_chat_handler,
         delete_chat_handler,
+        get_draft_handler,
+        save_draft_handler,
+        delete_draft_handler,
+        list_chat_members_handler,
+        add_chat_member_handler,
+        update_chat_member_handler,
+        remove_chat_member_handler,
+        create_invite_handler,
+        list_invites_handler,
+        revoke_invite_handler,
+        accept_invite_handler,
+        list_archived_chats_handler,
+        archive_chat_handler,
+        unarchive_chat_handler,
+        list_channels_handler,
+        join_chat_handler,
+        leave_chat_handler,
         create_chat_handler,
         list_message_handler,
+        get_message_context_handler,
         send_message_handler,
+        forward_message_handler,
+        list_scheduled_handler,
+        reschedule_message_handler,
+        cancel_scheduled_handler,
+        update_message_handler,
+        delete_message_handler,
+        list_thread_handler,
+        add_reaction_handler,
+        remove_reaction_handler,
+        list_mentions_handler,
+        pin_message_handler,
+        unpin_message_handler,
+        list_pins_handler,
+        vote_poll_handler,
+        save_message_handler,
+        unsave_message_handler,
+        list_saved_handler,
+        search_messages_handler,
+        mark_read_handler,
+        list_message_reads_handler,
+        list_message_revisions_handler,
         list_chat_handler,
         list_chat_users_handler,
         upload_handler,
         file_handler,
     ),
     components(
-        schemas(User, Chat, ChatType, ChatUser, Message, Workspace, SigninUser, CreateUser, CreateChat, CreateMessage, ListMessages, AuthOutput, ErrorOutput),
+        schemas(User, Chat, ChatType, ChatUser, ChatRole, ChatMember, ListMembers, UpdateMember, ListChannels, ChannelInfo, CreateInvite, ChatInvite, Message, ContentFormat, MessageRevision, Pin, Reaction, ReadReceipt, Draft, SaveDraft, Mention, MentionKind, Workspace, SigninUser, CreateUser, CreateChat, CreateMessage, ForwardMessage, MessageQuote, CreatePoll, VotePoll, Poll, PollOption, UpdateMessage, ListMessages, GetMessageContext, MessagePage, MessageContext, ListMentions, ListSaved, SaveMessage, SavedMessage, ScheduledMessage, RescheduleMessage, MarkRead, SearchMessages, SearchHit, SearchOutput, AuthOutput, ErrorOutput),
     ),
     modifiers(&SecurityAddon),
     tags(
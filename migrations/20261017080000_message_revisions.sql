@@ -0,0 +1,50 @@
+-- Add migration script here
+-- track when a message was last edited
+ALTER TABLE messages
+    ADD COLUMN updated_at timestamptz;
+
+-- keep every prior version of an edited message
+CREATE TABLE IF NOT EXISTS message_revisions
+(
+    id         bigserial PRIMARY KEY,
+    message_id bigint NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    content    text   NOT NULL,
+    files      text[] NOT NULL DEFAULT '{}',
+    created_at timestamptz     DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for message revisions for message_id
+CREATE INDEX IF NOT EXISTS message_revisions_message_id_index ON message_revisions (message_id, id DESC);
+
+-- if message added or updated, notify with message data
+CREATE OR REPLACE FUNCTION add_to_message()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    USERS bigint[];
+BEGIN
+    SELECT members
+    INTO USERS
+    FROM chats
+    WHERE id = NEW.chat_id;
+    IF TG_OP = 'INSERT' THEN
+        RAISE NOTICE 'add_to_message: %', NEW;
+        PERFORM
+            pg_notify('chat_message_created', json_build_object('message', NEW, 'members', USERS)::text);
+    ELSIF TG_OP = 'UPDATE' THEN
+        RAISE NOTICE 'update_message: %', NEW;
+        PERFORM
+            pg_notify('chat_message_updated', json_build_object('message', NEW, 'members', USERS)::text);
+    END IF;
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS add_to_message_trigger ON messages;
+
+CREATE TRIGGER add_to_message_trigger
+    AFTER INSERT OR UPDATE
+    ON messages
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_message();
@@ -0,0 +1,33 @@
+-- Add migration script here
+-- deleted messages are kept as tombstones
+ALTER TABLE messages
+    ADD COLUMN deleted_at timestamptz;
+
+-- if message added, updated or deleted, notify with message data
+CREATE OR REPLACE FUNCTION add_to_message()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    USERS bigint[];
+BEGIN
+    SELECT members
+    INTO USERS
+    FROM chats
+    WHERE id = NEW.chat_id;
+    IF TG_OP = 'INSERT' THEN
+        RAISE NOTICE 'add_to_message: %', NEW;
+        PERFORM
+            pg_notify('chat_message_created', json_build_object('message', NEW, 'members', USERS)::text);
+    ELSIF TG_OP = 'UPDATE' AND OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        RAISE NOTICE 'delete_message: %', NEW;
+        PERFORM
+            pg_notify('chat_message_deleted', json_build_object('message', NEW, 'members', USERS)::text);
+    ELSIF TG_OP = 'UPDATE' THEN
+        RAISE NOTICE 'update_message: %', NEW;
+        PERFORM
+            pg_notify('chat_message_updated', json_build_object('message', NEW, 'members', USERS)::text);
+    END IF;
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
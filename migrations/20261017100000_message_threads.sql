@@ -0,0 +1,7 @@
+-- Add migration script here
+-- replies point to the root message of their thread
+ALTER TABLE messages
+    ADD COLUMN parent_id bigint REFERENCES messages (id) ON DELETE CASCADE;
+
+-- create index for messages for parent_id
+CREATE INDEX IF NOT EXISTS parent_id_index ON messages (parent_id, id DESC) WHERE parent_id IS NOT NULL;
@@ -0,0 +1,54 @@
+-- Add migration script here
+-- create message reaction table, one row per user per emoji
+CREATE TABLE IF NOT EXISTS message_reactions
+(
+    message_id bigint      NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    user_id    bigint      NOT NULL REFERENCES users (id),
+    emoji      varchar(64) NOT NULL,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (message_id, user_id, emoji)
+);
+
+-- if reaction added or removed, notify with reaction data
+CREATE OR REPLACE FUNCTION add_to_reaction()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    REACTION message_reactions;
+    CHAT     bigint;
+    USERS    bigint[];
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        REACTION := NEW;
+    ELSE
+        REACTION := OLD;
+    END IF;
+    RAISE NOTICE 'add_to_reaction: %', REACTION;
+    SELECT c.id, c.members
+    INTO CHAT, USERS
+    FROM messages m
+             JOIN chats c ON c.id = m.chat_id
+    WHERE m.id = REACTION.message_id;
+    -- the message is already gone when its chat is deleted
+    IF CHAT IS NULL THEN
+        RETURN REACTION;
+    END IF;
+    PERFORM
+        pg_notify('message_reaction_changed', json_build_object(
+                'op', TG_OP,
+                'reaction', json_build_object(
+                        'chat_id', CHAT,
+                        'message_id', REACTION.message_id,
+                        'user_id', REACTION.user_id,
+                        'emoji', REACTION.emoji),
+                'members', USERS)::text);
+    RETURN REACTION;
+END;
+$$
+    LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_reaction_trigger
+    AFTER INSERT OR DELETE
+    ON message_reactions
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_reaction();
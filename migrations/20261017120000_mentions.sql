@@ -0,0 +1,47 @@
+-- Add migration script here
+-- create mention kind: user, here, channel
+CREATE TYPE mention_kind AS ENUM (
+    'user',
+    'here',
+    'channel'
+    );
+
+-- create mention table, a user is mentioned at most once per message
+CREATE TABLE IF NOT EXISTS mentions
+(
+    id         bigserial PRIMARY KEY,
+    message_id bigint       NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    chat_id    bigint       NOT NULL REFERENCES chats (id) ON DELETE CASCADE,
+    user_id    bigint       NOT NULL REFERENCES users (id),
+    kind       mention_kind NOT NULL,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (message_id, user_id)
+);
+
+-- create index for mentions for user_id
+CREATE INDEX IF NOT EXISTS mentions_user_id_index ON mentions (user_id, id DESC);
+
+-- if user mentioned, notify with mention and message data
+CREATE OR REPLACE FUNCTION add_to_mention()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    MESSAGE messages;
+BEGIN
+    RAISE NOTICE 'add_to_mention: %', NEW;
+    SELECT *
+    INTO MESSAGE
+    FROM messages
+    WHERE id = NEW.message_id;
+    PERFORM
+        pg_notify('message_mentioned', json_build_object('mention', NEW, 'message', MESSAGE)::text);
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_mention_trigger
+    AFTER INSERT
+    ON mentions
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_mention();
@@ -0,0 +1,29 @@
+-- Add migration script here
+-- create chat read table, the last message each member has read
+CREATE TABLE IF NOT EXISTS chat_reads
+(
+    chat_id              bigint NOT NULL REFERENCES chats (id) ON DELETE CASCADE,
+    user_id              bigint NOT NULL REFERENCES users (id),
+    last_read_message_id bigint NOT NULL,
+    updated_at           timestamptz DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (chat_id, user_id)
+);
+
+-- if read position changed, notify with read data
+CREATE OR REPLACE FUNCTION add_to_chat_read()
+    RETURNS TRIGGER AS
+$$
+BEGIN
+    RAISE NOTICE 'add_to_chat_read: %', NEW;
+    PERFORM
+        pg_notify('chat_read_updated', row_to_json(NEW)::text);
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_chat_read_trigger
+    AFTER INSERT OR UPDATE
+    ON chat_reads
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_chat_read();
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- last time the user was online, set when the last session closes
+ALTER TABLE users
+    ADD COLUMN last_seen_at timestamptz;
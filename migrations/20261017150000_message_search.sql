@@ -0,0 +1,3 @@
+-- Add migration script here
+-- create full text index for messages for content
+CREATE INDEX IF NOT EXISTS content_search_index ON messages USING GIN (to_tsvector('simple', content));
@@ -0,0 +1,50 @@
+-- Add migration script here
+-- create message pin table, a message can be pinned once in its chat
+CREATE TABLE IF NOT EXISTS message_pins
+(
+    message_id bigint PRIMARY KEY REFERENCES messages (id) ON DELETE CASCADE,
+    chat_id    bigint NOT NULL REFERENCES chats (id) ON DELETE CASCADE,
+    pinned_by  bigint NOT NULL REFERENCES users (id),
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for pins for chat_id order by created_at desc
+CREATE INDEX IF NOT EXISTS pin_chat_id_created_at_index ON message_pins (chat_id, created_at DESC);
+
+-- if message pinned or unpinned, notify with pin data
+CREATE OR REPLACE FUNCTION add_to_pin()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    PIN   message_pins;
+    USERS bigint[];
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        PIN := NEW;
+    ELSE
+        PIN := OLD;
+    END IF;
+    RAISE NOTICE 'add_to_pin: %', PIN;
+    SELECT members INTO USERS FROM chats WHERE id = PIN.chat_id;
+    -- the chat is already gone when it is deleted
+    IF USERS IS NULL THEN
+        RETURN PIN;
+    END IF;
+    PERFORM
+        pg_notify('message_pin_changed', json_build_object(
+                'op', TG_OP,
+                'pin', json_build_object(
+                        'chat_id', PIN.chat_id,
+                        'message_id', PIN.message_id,
+                        'pinned_by', PIN.pinned_by),
+                'members', USERS)::text);
+    RETURN PIN;
+END;
+$$
+    LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_pin_trigger
+    AFTER INSERT OR DELETE
+    ON message_pins
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_pin();
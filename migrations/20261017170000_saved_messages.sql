@@ -0,0 +1,15 @@
+-- Add migration script here
+-- create saved message table, the personal bookmarks of a user
+CREATE TABLE IF NOT EXISTS saved_messages
+(
+    id         bigserial PRIMARY KEY,
+    user_id    bigint NOT NULL REFERENCES users (id),
+    message_id bigint NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    note       text,
+    remind_at  timestamptz,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, message_id)
+);
+
+-- create index for saved messages for user_id order by id desc
+CREATE INDEX IF NOT EXISTS saved_user_id_index ON saved_messages (user_id, id DESC);
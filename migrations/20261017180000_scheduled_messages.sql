@@ -0,0 +1,19 @@
+-- Add migration script here
+-- create scheduled message table, pending messages are moved to messages when due
+CREATE TABLE IF NOT EXISTS scheduled_messages
+(
+    id         bigserial PRIMARY KEY,
+    chat_id    bigint      NOT NULL REFERENCES chats (id) ON DELETE CASCADE,
+    sender_id  bigint      NOT NULL REFERENCES users (id),
+    content    text        NOT NULL,
+    files      text[]      NOT NULL DEFAULT '{}',
+    parent_id  bigint REFERENCES messages (id) ON DELETE CASCADE,
+    send_at    timestamptz NOT NULL,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for scheduled messages for send_at, used by the dispatcher
+CREATE INDEX IF NOT EXISTS scheduled_send_at_index ON scheduled_messages (send_at);
+
+-- create index for scheduled messages for chat_id and sender_id
+CREATE INDEX IF NOT EXISTS scheduled_chat_id_sender_id_index ON scheduled_messages (chat_id, sender_id);
@@ -0,0 +1,61 @@
+-- Add migration script here
+-- messages of a chat with a ttl (in seconds) disappear after it expires
+ALTER TABLE chats
+    ADD COLUMN message_ttl integer CHECK (message_ttl > 0);
+
+ALTER TABLE messages
+    ADD COLUMN expires_at timestamptz;
+
+-- create index for messages for expires_at, used by the sweeper
+CREATE INDEX IF NOT EXISTS expires_at_index ON messages (expires_at) WHERE expires_at IS NOT NULL;
+
+-- if message added, updated or deleted, notify with message data
+-- expired messages are removed for good, notify them as deleted as well
+CREATE OR REPLACE FUNCTION add_to_message()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    USERS bigint[];
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        SELECT members
+        INTO USERS
+        FROM chats
+        WHERE id = OLD.chat_id;
+        -- the chat is already gone when it is deleted
+        IF USERS IS NOT NULL THEN
+            RAISE NOTICE 'expire_message: %', OLD;
+            PERFORM
+                pg_notify('chat_message_deleted', json_build_object('message', OLD, 'members', USERS)::text);
+        END IF;
+        RETURN OLD;
+    END IF;
+    SELECT members
+    INTO USERS
+    FROM chats
+    WHERE id = NEW.chat_id;
+    IF TG_OP = 'INSERT' THEN
+        RAISE NOTICE 'add_to_message: %', NEW;
+        PERFORM
+            pg_notify('chat_message_created', json_build_object('message', NEW, 'members', USERS)::text);
+    ELSIF TG_OP = 'UPDATE' AND OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        RAISE NOTICE 'delete_message: %', NEW;
+        PERFORM
+            pg_notify('chat_message_deleted', json_build_object('message', NEW, 'members', USERS)::text);
+    ELSIF TG_OP = 'UPDATE' THEN
+        RAISE NOTICE 'update_message: %', NEW;
+        PERFORM
+            pg_notify('chat_message_updated', json_build_object('message', NEW, 'members', USERS)::text);
+    END IF;
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS add_to_message_trigger ON messages;
+
+CREATE TRIGGER add_to_message_trigger
+    AFTER INSERT OR UPDATE OR DELETE
+    ON messages
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_message();
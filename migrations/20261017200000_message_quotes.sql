@@ -0,0 +1,7 @@
+-- Add migration script here
+-- snapshot of the quoted or forwarded message, kept when the original is edited or deleted
+ALTER TABLE messages
+    ADD COLUMN quote jsonb;
+
+ALTER TABLE scheduled_messages
+    ADD COLUMN quote_of bigint;
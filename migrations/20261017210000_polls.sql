@@ -0,0 +1,68 @@
+-- Add migration script here
+-- create poll table, a poll is attached to the message it is sent with
+CREATE TABLE IF NOT EXISTS polls
+(
+    message_id bigint PRIMARY KEY REFERENCES messages (id) ON DELETE CASCADE,
+    question   text    NOT NULL,
+    options    text[]  NOT NULL,
+    multiple   boolean NOT NULL DEFAULT FALSE,
+    anonymous  boolean NOT NULL DEFAULT FALSE,
+    closes_at  timestamptz,
+    updated_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create poll vote table, one row per user per chosen option
+CREATE TABLE IF NOT EXISTS poll_votes
+(
+    message_id   bigint  NOT NULL REFERENCES polls (message_id) ON DELETE CASCADE,
+    user_id      bigint  NOT NULL REFERENCES users (id),
+    option_index integer NOT NULL,
+    created_at   timestamptz DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (message_id, user_id, option_index)
+);
+
+-- if votes of a poll changed, notify with the tallies
+CREATE OR REPLACE FUNCTION add_to_poll()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    CHAT   bigint;
+    USERS  bigint[];
+    COUNTS bigint[];
+    VOTERS bigint;
+BEGIN
+    RAISE NOTICE 'add_to_poll: %', NEW;
+    SELECT c.id, c.members
+    INTO CHAT, USERS
+    FROM messages m
+             JOIN chats c ON c.id = m.chat_id
+    WHERE m.id = NEW.message_id;
+    SELECT array_agg(COALESCE(v.count, 0) ORDER BY i)
+    INTO COUNTS
+    FROM generate_subscripts(NEW.options, 1) i
+             LEFT JOIN (SELECT option_index, COUNT(*) AS count
+                        FROM poll_votes
+                        WHERE message_id = NEW.message_id
+                        GROUP BY option_index) v ON v.option_index = i - 1;
+    SELECT COUNT(DISTINCT user_id)
+    INTO VOTERS
+    FROM poll_votes
+    WHERE message_id = NEW.message_id;
+    PERFORM
+        pg_notify('poll_updated', json_build_object(
+                'poll', json_build_object(
+                        'chat_id', CHAT,
+                        'message_id', NEW.message_id,
+                        'counts', COUNTS,
+                        'voters', VOTERS),
+                'members', USERS)::text);
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_poll_trigger
+    AFTER UPDATE
+    ON polls
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_poll();
@@ -0,0 +1,21 @@
+-- Add migration script here
+CREATE TYPE message_format AS ENUM ('plain', 'markdown');
+
+-- the rendered html and the plain text fallback are stored alongside the content
+ALTER TABLE messages
+    ADD COLUMN format message_format NOT NULL DEFAULT 'plain',
+    ADD COLUMN html text NOT NULL DEFAULT '',
+    ADD COLUMN plain_text text NOT NULL DEFAULT '';
+
+-- existing messages are plain text; the backfill must not notify every row
+ALTER TABLE messages DISABLE TRIGGER add_to_message_trigger;
+
+UPDATE messages
+SET plain_text = content,
+    html = '<p>' || replace(replace(replace(replace(replace(content, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '"', '&quot;'), E'\n', '<br>') || '</p>'
+WHERE content <> '';
+
+ALTER TABLE messages ENABLE TRIGGER add_to_message_trigger;
+
+ALTER TABLE scheduled_messages
+    ADD COLUMN format message_format NOT NULL DEFAULT 'plain';
@@ -0,0 +1,40 @@
+-- Add migration script here
+-- one unsent draft per user per chat, synced across the sessions of the user
+CREATE TABLE IF NOT EXISTS chat_drafts
+(
+    chat_id    bigint NOT NULL REFERENCES chats (id) ON DELETE CASCADE,
+    user_id    bigint NOT NULL REFERENCES users (id),
+    content    text   NOT NULL DEFAULT '',
+    files      text[] NOT NULL DEFAULT '{}',
+    updated_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (chat_id, user_id)
+);
+
+-- notify the user with the draft, a deleted draft is sent as an empty one
+CREATE OR REPLACE FUNCTION add_to_chat_draft()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    draft chat_drafts;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        draft := OLD;
+        draft.content := '';
+        draft.files := '{}';
+        draft.updated_at := NOW();
+    ELSE
+        draft := NEW;
+    END IF;
+    RAISE NOTICE 'add_to_chat_draft: %', draft;
+    PERFORM
+        pg_notify('chat_draft_updated', row_to_json(draft)::text);
+    RETURN NULL;
+END;
+$$
+    LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_chat_draft_trigger
+    AFTER INSERT OR UPDATE OR DELETE
+    ON chat_drafts
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_chat_draft();
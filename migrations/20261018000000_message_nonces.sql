@@ -0,0 +1,7 @@
+-- Add migration script here
+-- nonce chosen by the client to dedupe retried sends
+ALTER TABLE messages
+    ADD COLUMN client_nonce text;
+
+CREATE INDEX IF NOT EXISTS messages_client_nonce_index ON messages (chat_id, sender_id, client_nonce)
+    WHERE client_nonce IS NOT NULL;
@@ -0,0 +1,297 @@
+-- Add migration script here
+CREATE TYPE chat_role AS ENUM ('owner', 'admin', 'member');
+
+-- create chat member table, replaces chats.members
+CREATE TABLE IF NOT EXISTS chat_members
+(
+    chat_id   bigint      NOT NULL REFERENCES chats (id) ON DELETE CASCADE,
+    user_id   bigint      NOT NULL REFERENCES users (id),
+    role      chat_role   NOT NULL DEFAULT 'member',
+    joined_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- null for the members migrated from chats.members
+    added_by  bigint REFERENCES users (id),
+    PRIMARY KEY (chat_id, user_id)
+);
+
+-- create index for chat members for user_id, used to list the chats of a user
+CREATE INDEX IF NOT EXISTS chat_members_user_id_index ON chat_members (user_id);
+
+-- the workspace owner owns the chats it is a member of, otherwise the first member does
+INSERT INTO chat_members (chat_id, user_id, role, joined_at)
+SELECT c.id,
+       m.user_id,
+       CASE
+           WHEN m.user_id = CASE WHEN w.owner_id = ANY (c.members) THEN w.owner_id ELSE c.members[1] END
+               THEN 'owner'::chat_role
+           ELSE 'member'::chat_role
+           END,
+       COALESCE(c.created_at, NOW())
+FROM chats c
+         JOIN workspaces w ON w.id = c.ws_id
+         CROSS JOIN LATERAL unnest(c.members) AS m(user_id)
+WHERE EXISTS (SELECT 1 FROM users u WHERE u.id = m.user_id)
+ON CONFLICT DO NOTHING;
+
+-- member ids of a chat in the order they joined
+CREATE OR REPLACE FUNCTION chat_member_ids(chat bigint)
+    RETURNS bigint[] AS
+$$
+SELECT ARRAY(SELECT user_id FROM chat_members WHERE chat_id = chat ORDER BY joined_at, user_id);
+$$
+    LANGUAGE sql STABLE;
+
+-- chat data with its member ids, as notify-server expects
+CREATE OR REPLACE FUNCTION chat_to_json(chat chats, members bigint[])
+    RETURNS jsonb AS
+$$
+SELECT to_jsonb(chat) || jsonb_build_object('members', members);
+$$
+    LANGUAGE sql STABLE;
+
+-- if chat changed, notify with chat data
+-- a new chat is notified on commit when its members are in place,
+-- a deleted chat before its members are removed along with it
+CREATE OR REPLACE FUNCTION add_to_chat()
+    RETURNS TRIGGER AS
+$$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        RAISE NOTICE 'add_to_chat: %', OLD;
+        PERFORM
+            pg_notify('chat_updated', json_build_object(
+                    'op', TG_OP,
+                    'old', chat_to_json(OLD, chat_member_ids(OLD.id)),
+                    'new', NULL)::text);
+        RETURN OLD;
+    END IF;
+    RAISE NOTICE 'add_to_chat: %', NEW;
+    IF TG_OP = 'INSERT' THEN
+        PERFORM
+            pg_notify('chat_updated', json_build_object(
+                    'op', TG_OP,
+                    'old', NULL,
+                    'new', chat_to_json(NEW, chat_member_ids(NEW.id)))::text);
+    ELSE
+        PERFORM
+            pg_notify('chat_updated', json_build_object(
+                    'op', TG_OP,
+                    'old', chat_to_json(OLD, chat_member_ids(OLD.id)),
+                    'new', chat_to_json(NEW, chat_member_ids(NEW.id)))::text);
+    END IF;
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS add_to_chat_trigger ON chats;
+
+CREATE CONSTRAINT TRIGGER add_to_chat_insert_trigger
+    AFTER INSERT
+    ON chats
+    DEFERRABLE INITIALLY DEFERRED
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_chat();
+
+CREATE TRIGGER add_to_chat_update_trigger
+    AFTER UPDATE
+    ON chats
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_chat();
+
+CREATE TRIGGER add_to_chat_delete_trigger
+    BEFORE DELETE
+    ON chats
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_chat();
+
+-- if a member joined or left, notify the chat update with the members before and after
+CREATE OR REPLACE FUNCTION add_to_chat_member()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    MEMBER      chat_members;
+    CHAT        chats;
+    MEMBERS     bigint[];
+    OLD_MEMBERS bigint[];
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        MEMBER := NEW;
+    ELSE
+        MEMBER := OLD;
+    END IF;
+    SELECT * INTO CHAT FROM chats WHERE id = MEMBER.chat_id;
+    -- the chat is already gone when it is deleted
+    IF NOT FOUND THEN
+        RETURN NULL;
+    END IF;
+    -- the first members are notified along with the new chat
+    IF TG_OP = 'INSERT' AND NEW.joined_at = CHAT.created_at THEN
+        RETURN NULL;
+    END IF;
+    RAISE NOTICE 'add_to_chat_member: %', MEMBER;
+    MEMBERS := chat_member_ids(CHAT.id);
+    IF TG_OP = 'INSERT' THEN
+        OLD_MEMBERS := array_remove(MEMBERS, MEMBER.user_id);
+    ELSE
+        OLD_MEMBERS := MEMBERS || MEMBER.user_id;
+    END IF;
+    PERFORM
+        pg_notify('chat_updated', json_build_object(
+                'op', 'UPDATE',
+                'old', chat_to_json(CHAT, OLD_MEMBERS),
+                'new', chat_to_json(CHAT, MEMBERS))::text);
+    RETURN NULL;
+END;
+$$
+    LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_chat_member_trigger
+    AFTER INSERT OR DELETE
+    ON chat_members
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_chat_member();
+
+-- if message added, updated or deleted, notify with message data
+CREATE OR REPLACE FUNCTION add_to_message()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    USERS bigint[];
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        -- the chat is already gone when it is deleted
+        IF EXISTS (SELECT 1 FROM chats WHERE id = OLD.chat_id) THEN
+            RAISE NOTICE 'expire_message: %', OLD;
+            USERS := chat_member_ids(OLD.chat_id);
+            PERFORM
+                pg_notify('chat_message_deleted', json_build_object('message', OLD, 'members', USERS)::text);
+        END IF;
+        RETURN OLD;
+    END IF;
+    USERS := chat_member_ids(NEW.chat_id);
+    IF TG_OP = 'INSERT' THEN
+        RAISE NOTICE 'add_to_message: %', NEW;
+        PERFORM
+            pg_notify('chat_message_created', json_build_object('message', NEW, 'members', USERS)::text);
+    ELSIF TG_OP = 'UPDATE' AND OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        RAISE NOTICE 'delete_message: %', NEW;
+        PERFORM
+            pg_notify('chat_message_deleted', json_build_object('message', NEW, 'members', USERS)::text);
+    ELSIF TG_OP = 'UPDATE' THEN
+        RAISE NOTICE 'update_message: %', NEW;
+        PERFORM
+            pg_notify('chat_message_updated', json_build_object('message', NEW, 'members', USERS)::text);
+    END IF;
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+-- if reaction added or removed, notify with reaction data
+CREATE OR REPLACE FUNCTION add_to_reaction()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    REACTION message_reactions;
+    CHAT     bigint;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        REACTION := NEW;
+    ELSE
+        REACTION := OLD;
+    END IF;
+    RAISE NOTICE 'add_to_reaction: %', REACTION;
+    SELECT c.id
+    INTO CHAT
+    FROM messages m
+             JOIN chats c ON c.id = m.chat_id
+    WHERE m.id = REACTION.message_id;
+    -- the message is already gone when its chat is deleted
+    IF CHAT IS NULL THEN
+        RETURN REACTION;
+    END IF;
+    PERFORM
+        pg_notify('message_reaction_changed', json_build_object(
+                'op', TG_OP,
+                'reaction', json_build_object(
+                        'chat_id', CHAT,
+                        'message_id', REACTION.message_id,
+                        'user_id', REACTION.user_id,
+                        'emoji', REACTION.emoji),
+                'members', chat_member_ids(CHAT))::text);
+    RETURN REACTION;
+END;
+$$
+    LANGUAGE plpgsql;
+
+-- if message pinned or unpinned, notify with pin data
+CREATE OR REPLACE FUNCTION add_to_pin()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    PIN message_pins;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        PIN := NEW;
+    ELSE
+        PIN := OLD;
+    END IF;
+    RAISE NOTICE 'add_to_pin: %', PIN;
+    -- the chat is already gone when it is deleted
+    IF NOT EXISTS (SELECT 1 FROM chats WHERE id = PIN.chat_id) THEN
+        RETURN PIN;
+    END IF;
+    PERFORM
+        pg_notify('message_pin_changed', json_build_object(
+                'op', TG_OP,
+                'pin', json_build_object(
+                        'chat_id', PIN.chat_id,
+                        'message_id', PIN.message_id,
+                        'pinned_by', PIN.pinned_by),
+                'members', chat_member_ids(PIN.chat_id))::text);
+    RETURN PIN;
+END;
+$$
+    LANGUAGE plpgsql;
+
+-- if votes of a poll changed, notify with the tallies
+CREATE OR REPLACE FUNCTION add_to_poll()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    CHAT   bigint;
+    COUNTS bigint[];
+    VOTERS bigint;
+BEGIN
+    RAISE NOTICE 'add_to_poll: %', NEW;
+    SELECT m.chat_id
+    INTO CHAT
+    FROM messages m
+    WHERE m.id = NEW.message_id;
+    SELECT array_agg(COALESCE(v.count, 0) ORDER BY i)
+    INTO COUNTS
+    FROM generate_subscripts(NEW.options, 1) i
+             LEFT JOIN (SELECT option_index, COUNT(*) AS count
+                        FROM poll_votes
+                        WHERE message_id = NEW.message_id
+                        GROUP BY option_index) v ON v.option_index = i - 1;
+    SELECT COUNT(DISTINCT user_id)
+    INTO VOTERS
+    FROM poll_votes
+    WHERE message_id = NEW.message_id;
+    PERFORM
+        pg_notify('poll_updated', json_build_object(
+                'poll', json_build_object(
+                        'chat_id', CHAT,
+                        'message_id', NEW.message_id,
+                        'counts', COUNTS,
+                        'voters', VOTERS),
+                'members', chat_member_ids(CHAT))::text);
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+-- the unique constraint and the index on members are dropped along with it
+ALTER TABLE chats
+    DROP COLUMN members;
@@ -0,0 +1,36 @@
+-- Add migration script here
+-- if a member joined or left, notify the member along with the chat and its members after the change,
+-- the members are notified of their own removal as well
+CREATE OR REPLACE FUNCTION add_to_chat_member()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    MEMBER chat_members;
+    CHAT   chats;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        MEMBER := NEW;
+    ELSE
+        MEMBER := OLD;
+    END IF;
+    SELECT * INTO CHAT FROM chats WHERE id = MEMBER.chat_id;
+    -- the chat is already gone when it is deleted
+    IF NOT FOUND THEN
+        RETURN NULL;
+    END IF;
+    -- the first members are notified along with the new chat
+    IF TG_OP = 'INSERT' AND NEW.joined_at = CHAT.created_at THEN
+        RETURN NULL;
+    END IF;
+    RAISE NOTICE 'add_to_chat_member: %', MEMBER;
+    PERFORM
+        pg_notify('chat_member_changed', json_build_object(
+                'op', TG_OP,
+                'user_id', MEMBER.user_id,
+                'role', MEMBER.role,
+                'added_by', MEMBER.added_by,
+                'chat', chat_to_json(CHAT, chat_member_ids(CHAT.id)))::text);
+    RETURN NULL;
+END;
+$$
+    LANGUAGE plpgsql;
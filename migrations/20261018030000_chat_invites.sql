@@ -0,0 +1,19 @@
+-- Add migration script here
+-- create chat invite table, an invite adds the user who accepts it to the chat
+CREATE TABLE IF NOT EXISTS chat_invites
+(
+    id         bigserial PRIMARY KEY,
+    chat_id    bigint      NOT NULL REFERENCES chats (id) ON DELETE CASCADE,
+    code       varchar(32) NOT NULL UNIQUE,
+    created_by bigint      NOT NULL REFERENCES users (id),
+    -- null for invites which never expire
+    expires_at timestamptz,
+    -- null for invites which can be used any number of times
+    max_uses   int CHECK (max_uses > 0),
+    uses       int         NOT NULL DEFAULT 0,
+    revoked_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for chat invites for chat_id
+CREATE INDEX IF NOT EXISTS chat_invites_chat_id_index ON chat_invites (chat_id);
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- archived chats keep their history but do not accept new messages
+ALTER TABLE chats
+    ADD COLUMN archived_at timestamptz;
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- scheduled messages which can not be sent are kept and marked as failed
+ALTER TABLE scheduled_messages
+    ADD COLUMN failed_at timestamptz,
+    ADD COLUMN error text;
@@ -0,0 +1,73 @@
+-- Add migration script here
+-- the message sent in notifications, without the rendered html and plain text
+-- so the payload stays below the 8000 bytes pg_notify limit
+CREATE OR REPLACE FUNCTION message_payload(m messages)
+    RETURNS json AS
+$$
+SELECT json_build_object('id', m.id, 'chat_id', m.chat_id, 'sender_id', m.sender_id,
+                         'content', m.content, 'files', m.files, 'created_at', m.created_at,
+                         'updated_at', m.updated_at, 'deleted_at', m.deleted_at,
+                         'parent_id', m.parent_id, 'expires_at', m.expires_at, 'quote', m.quote,
+                         'format', m.format, 'client_nonce', m.client_nonce)
+$$
+    LANGUAGE sql;
+
+-- if message added, updated or deleted, notify with message data
+CREATE OR REPLACE FUNCTION add_to_message()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    USERS bigint[];
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        -- the chat is already gone when it is deleted
+        IF EXISTS (SELECT 1 FROM chats WHERE id = OLD.chat_id) THEN
+            RAISE NOTICE 'expire_message: %', OLD.id;
+            USERS := chat_member_ids(OLD.chat_id);
+            PERFORM
+                pg_notify('chat_message_deleted',
+                          json_build_object('message', message_payload(OLD), 'members', USERS)::text);
+        END IF;
+        RETURN OLD;
+    END IF;
+    USERS := chat_member_ids(NEW.chat_id);
+    IF TG_OP = 'INSERT' THEN
+        RAISE NOTICE 'add_to_message: %', NEW.id;
+        PERFORM
+            pg_notify('chat_message_created',
+                      json_build_object('message', message_payload(NEW), 'members', USERS)::text);
+    ELSIF TG_OP = 'UPDATE' AND OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        RAISE NOTICE 'delete_message: %', NEW.id;
+        PERFORM
+            pg_notify('chat_message_deleted',
+                      json_build_object('message', message_payload(NEW), 'members', USERS)::text);
+    ELSIF TG_OP = 'UPDATE' THEN
+        RAISE NOTICE 'update_message: %', NEW.id;
+        PERFORM
+            pg_notify('chat_message_updated',
+                      json_build_object('message', message_payload(NEW), 'members', USERS)::text);
+    END IF;
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+-- if user mentioned, notify with mention and message data
+CREATE OR REPLACE FUNCTION add_to_mention()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    MESSAGE messages;
+BEGIN
+    RAISE NOTICE 'add_to_mention: %', NEW;
+    SELECT *
+    INTO MESSAGE
+    FROM messages
+    WHERE id = NEW.message_id;
+    PERFORM
+        pg_notify('message_mentioned',
+                  json_build_object('mention', NEW, 'message', message_payload(MESSAGE))::text);
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
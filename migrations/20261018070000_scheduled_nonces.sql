@@ -0,0 +1,7 @@
+-- Add migration script here
+-- nonce chosen by the client to dedupe retried schedules, passed on to the sent message
+ALTER TABLE scheduled_messages
+    ADD COLUMN client_nonce text;
+
+CREATE INDEX IF NOT EXISTS scheduled_client_nonce_index ON scheduled_messages (chat_id, sender_id, client_nonce)
+    WHERE client_nonce IS NOT NULL;
@@ -0,0 +1,39 @@
+-- Add migration script here
+-- set by the reminder sweeper once the user is reminded, saving with a new remind_at resets it
+ALTER TABLE saved_messages
+    ADD COLUMN reminded_at timestamptz;
+
+-- create index for saved messages for pending reminders, used by the sweeper
+CREATE INDEX IF NOT EXISTS saved_remind_at_index ON saved_messages (remind_at)
+    WHERE remind_at IS NOT NULL AND reminded_at IS NULL;
+
+-- if a reminder is due, notify the user with the saved message,
+-- deleted messages and chats the user left are skipped
+CREATE OR REPLACE FUNCTION add_to_saved_reminder()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    MESSAGE messages;
+BEGIN
+    SELECT m.*
+    INTO MESSAGE
+    FROM messages m
+             JOIN chat_members cm ON cm.chat_id = m.chat_id AND cm.user_id = NEW.user_id
+    WHERE m.id = NEW.message_id AND m.deleted_at IS NULL;
+    IF FOUND THEN
+        RAISE NOTICE 'add_to_saved_reminder: %', NEW;
+        PERFORM
+            pg_notify('saved_message_reminder',
+                      json_build_object('saved', NEW, 'message', message_payload(MESSAGE))::text);
+    END IF;
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_saved_reminder_trigger
+    AFTER UPDATE OF reminded_at
+    ON saved_messages
+    FOR EACH ROW
+    WHEN (OLD.reminded_at IS NULL AND NEW.reminded_at IS NOT NULL)
+EXECUTE FUNCTION add_to_saved_reminder();
@@ -9,6 +9,15 @@ pub enum AppError {
 
     #[error("io error: {0}")]
     IoError(#[from] std::io::Error),
+
+    #[error("sqlx error: {0}")]
+    SqlxError(#[from] sqlx::Error),
+
+    #[error("not a chat member: {0}")]
+    NotChatMember(String),
+
+    #[error("invalid input: {0}")]
+    InvalidInput(String),
 }
 
 impl IntoResponse for AppError {
@@ -16,6 +25,9 @@ impl IntoResponse for AppError {
         let status = match &self {
             AppError::JwtError(_) => StatusCode::FORBIDDEN,
             AppError::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::SqlxError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::NotChatMember(_) => StatusCode::FORBIDDEN,
+            AppError::InvalidInput(_) => StatusCode::BAD_REQUEST,
         };
         (status, Json(ErrorOutput::new(self.to_string()))).into_response()
     }
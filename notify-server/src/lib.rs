@@ -1,7 +1,9 @@
 mod config;
 mod error;
 mod notify;
+mod presence;
 mod sse;
+mod typing;
 
 use anyhow::Context;
 use axum::http::Method;
@@ -16,13 +18,17 @@ use crate::notify::AppEvent;
 use axum::middleware::from_fn_with_state;
 use axum::{
     response::{Html, IntoResponse},
-    routing::get,
+    routing::{get, post},
     Router,
 };
 use chat_core::{verify_token, DecodingKey, TokenVerify, User};
 use dashmap::DashMap;
+use presence::{get_presence_handler, heartbeat_handler, setup_presence_sweeper, PresenceStatus};
+use sqlx::PgPool;
 use sse::sse_handler;
 use tokio::sync::broadcast;
+use tokio::time::Instant;
+use typing::{setup_typing_sweeper, stop_typing_handler, typing_handler, TypingMap};
 
 pub use notify::setup_pg_listener;
 
@@ -31,6 +37,9 @@ type UserMap = Arc<DashMap<u64, SenderReceiverCnt>>;
 pub struct SenderReceiverCnt {
     sender: broadcast::Sender<Arc<AppEvent>>,
     cnt: usize,
+    ws_id: i64,
+    status: PresenceStatus,
+    last_active: Instant,
 }
 
 impl Deref for SenderReceiverCnt {
@@ -42,8 +51,14 @@ impl Deref for SenderReceiverCnt {
 }
 
 impl SenderReceiverCnt {
-    pub fn new(sender: broadcast::Sender<Arc<AppEvent>>) -> Self {
-        Self { sender, cnt: 0 }
+    pub fn new(sender: broadcast::Sender<Arc<AppEvent>>, ws_id: i64) -> Self {
+        Self {
+            sender,
+            cnt: 0,
+            ws_id,
+            status: PresenceStatus::Online,
+            last_active: Instant::now(),
+        }
     }
 
     pub fn reduce(&mut self) {
@@ -53,6 +68,12 @@ impl SenderReceiverCnt {
     pub fn increase(&mut self) {
         self.cnt += 1;
     }
+
+    /// Record user activity, the user is online again.
+    pub fn touch(&mut self) {
+        self.status = PresenceStatus::Online;
+        self.last_active = Instant::now();
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -62,13 +83,44 @@ pub struct AppStateInner {
     pub config: AppConfig,
     pub dk: DecodingKey,
     pub users: UserMap,
+    pub typing: TypingMap,
+    pub pool: PgPool,
 }
 
 impl AppState {
     pub async fn try_new(config: AppConfig) -> Result<Self, AppError> {
         let dk = DecodingKey::load(&config.auth.pk).context("load pk failed")?;
         let users = Arc::new(DashMap::new());
-        Ok(Self(Arc::new(AppStateInner { config, dk, users })))
+        let pool = PgPool::connect(&config.server.db_url).await?;
+        Ok(Self(Arc::new(AppStateInner {
+            config,
+            dk,
+            users,
+            typing: TypingMap::default(),
+            pool,
+        })))
+    }
+
+    /// Fetch the members of the chat, the user must be one of them.
+    pub async fn fetch_chat_members(
+        &self,
+        chat_id: i64,
+        user_id: i64,
+    ) -> Result<Vec<i64>, AppError> {
+        let members: Option<Vec<i64>> = sqlx::query_scalar(
+            r#"
+            SELECT chat_member_ids(chat_id)
+            FROM chat_members
+            WHERE chat_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(chat_id)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        members.ok_or_else(|| {
+            AppError::NotChatMember(format!("user {user_id} is not a member of chat {chat_id}"))
+        })
     }
 }
 
@@ -101,6 +153,8 @@ const INDEX_HTML: &str = include_str!("../index.html");
 pub async fn get_router(config: AppConfig) -> anyhow::Result<Router> {
     let app_state = AppState::try_new(config).await.expect("init failed");
     setup_pg_listener(app_state.clone()).await?;
+    setup_typing_sweeper(app_state.clone());
+    setup_presence_sweeper(app_state.clone());
     let cors = CorsLayer::new()
         // allow `GET` and `POST` when accessing the resource
         .allow_methods([
@@ -114,6 +168,12 @@ pub async fn get_router(config: AppConfig) -> anyhow::Result<Router> {
         .allow_headers(cors::Any);
     let router = Router::new()
         .route("/events", get(sse_handler))
+        .route("/presence", get(get_presence_handler))
+        .route("/presence/heartbeat", post(heartbeat_handler))
+        .route(
+            "/typing/{chat_id}",
+            post(typing_handler).delete(stop_typing_handler),
+        )
         .layer(from_fn_with_state(
             app_state.clone(),
             verify_token::<AppState>,
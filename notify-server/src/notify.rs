@@ -1,8 +1,11 @@
-use crate::AppState;
-use chat_core::{Chat, Message};
+use crate::presence::Presence;
+use crate::typing::{remove_typing_member, Typing};
+use crate::{AppState, UserMap};
+use chat_core::{Chat, ChatRole, Draft, Mention, MentionKind, Message, ReadReceipt};
 use jwt_simple::reexports::serde_json;
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgListener;
+use sqlx::types::chrono::{DateTime, Utc};
 use std::collections::HashSet;
 use std::sync::Arc;
 use tokio_stream::StreamExt;
@@ -16,19 +19,139 @@ struct ChatUpdated {
 }
 
 #[derive(Debug, Deserialize)]
-struct ChatMessageCreated {
+struct ChatMemberChanged {
+    op: String,
+    #[serde(flatten)]
+    member: MemberChanged,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct MemberChanged {
+    user_id: i64,
+    role: ChatRole,
+    added_by: Option<i64>,
+    /// the chat with its members after the change
+    chat: Chat,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessageChanged {
     message: Message,
     members: Vec<i64>,
 }
 
+#[derive(Debug, Deserialize)]
+struct MessageReactionChanged {
+    op: String,
+    reaction: ReactionChanged,
+    members: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ReactionChanged {
+    chat_id: i64,
+    message_id: i64,
+    user_id: i64,
+    emoji: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessagePinChanged {
+    op: String,
+    pin: PinChanged,
+    members: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct PinChanged {
+    chat_id: i64,
+    message_id: i64,
+    pinned_by: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct PollChanged {
+    poll: PollUpdated,
+    members: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct PollUpdated {
+    chat_id: i64,
+    message_id: i64,
+    /// votes of each option
+    counts: Vec<i64>,
+    voters: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageMentioned {
+    mention: MentionRow,
+    message: Message,
+}
+
+#[derive(Debug, Deserialize)]
+struct SavedReminder {
+    saved: SavedRow,
+    message: Message,
+}
+
+#[derive(Debug, Deserialize)]
+struct SavedRow {
+    id: i64,
+    user_id: i64,
+    note: Option<String>,
+    remind_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Reminder {
+    /// id of the saved message
+    id: i64,
+    note: Option<String>,
+    remind_at: DateTime<Utc>,
+    message: Message,
+}
+
+#[derive(Debug, Deserialize)]
+struct MentionRow {
+    id: i64,
+    user_id: i64,
+    kind: MentionKind,
+    created_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum AppEvent {
     NewChat(Chat),
+    /// deprecated, sent along with `MemberAdded` and `MemberRemoved` for one more release
     AddToChat(Chat),
     ChatNameUpdate(Chat),
     RemoveFromChat(Chat),
+    ChatArchived(Chat),
+    ChatUnarchived(Chat),
+    MemberAdded(MemberChanged),
+    MemberRemoved(MemberChanged),
     NewMessage(Message),
+    MessageUpdated(Message),
+    MessageDeleted(Message),
+    ReactionAdded(ReactionChanged),
+    ReactionRemoved(ReactionChanged),
+    MessagePinned(PinChanged),
+    MessageUnpinned(PinChanged),
+    PollUpdated(PollUpdated),
+    Mentioned(Mention),
+    ReadUpdated(ReadReceipt),
+    DraftUpdated(Draft),
+    SavedMessageReminder(Reminder),
+    Typing(Typing),
+    PresenceChanged(Presence),
 }
 
 #[derive(Debug)]
@@ -47,14 +170,13 @@ impl Notification {
                     get_affected_chat_user_ids(data.old.as_ref(), data.new.as_ref());
                 let event = match data.op.as_str() {
                     "INSERT" => AppEvent::NewChat(data.new.expect("new should exist")),
+                    // membership changes are notified on chat_member_changed
                     "UPDATE" => match (data.old, data.new) {
-                        (Some(old), Some(new)) => {
-                            if old.members.eq(&new.members) {
-                                AppEvent::ChatNameUpdate(new)
-                            } else {
-                                AppEvent::AddToChat(new)
-                            }
-                        }
+                        (Some(old), Some(new)) => match (old.archived_at, new.archived_at) {
+                            (None, Some(_)) => AppEvent::ChatArchived(new),
+                            (Some(_), None) => AppEvent::ChatUnarchived(new),
+                            _ => AppEvent::ChatNameUpdate(new),
+                        },
                         (_, _) => {
                             return Err(anyhow::anyhow!("update should have both old and new"))
                         }
@@ -67,14 +189,116 @@ impl Notification {
                     event: Arc::new(event),
                 })
             }
+            "chat_member_changed" => {
+                let data: ChatMemberChanged = serde_json::from_str(payload)?;
+                let member = data.member;
+                // the removed member gets the final event, later ones only reach the others
+                let mut affect_users: HashSet<_> =
+                    member.chat.members.iter().map(|v| *v as u64).collect();
+                affect_users.insert(member.user_id as u64);
+                let event = match data.op.as_str() {
+                    "INSERT" => AppEvent::MemberAdded(member),
+                    "DELETE" => AppEvent::MemberRemoved(member),
+                    _ => return Err(anyhow::anyhow!("Invalid operation: {}", data.op)),
+                };
+                Ok(Self {
+                    affect_users,
+                    event: Arc::new(event),
+                })
+            }
             "chat_message_created" => {
-                let data: ChatMessageCreated =
+                let data: ChatMessageChanged =
                     serde_json::from_str(payload).expect("failed to parse");
                 Ok(Self {
                     affect_users: data.members.into_iter().map(|v| v as u64).collect(),
                     event: Arc::new(AppEvent::NewMessage(data.message)),
                 })
             }
+            "chat_message_updated" => {
+                let data: ChatMessageChanged = serde_json::from_str(payload)?;
+                Ok(Self {
+                    affect_users: data.members.into_iter().map(|v| v as u64).collect(),
+                    event: Arc::new(AppEvent::MessageUpdated(data.message)),
+                })
+            }
+            "chat_message_deleted" => {
+                let data: ChatMessageChanged = serde_json::from_str(payload)?;
+                Ok(Self {
+                    affect_users: data.members.into_iter().map(|v| v as u64).collect(),
+                    event: Arc::new(AppEvent::MessageDeleted(data.message)),
+                })
+            }
+            "message_reaction_changed" => {
+                let data: MessageReactionChanged = serde_json::from_str(payload)?;
+                let event = match data.op.as_str() {
+                    "INSERT" => AppEvent::ReactionAdded(data.reaction),
+                    "DELETE" => AppEvent::ReactionRemoved(data.reaction),
+                    _ => return Err(anyhow::anyhow!("Invalid operation: {}", data.op)),
+                };
+                Ok(Self {
+                    affect_users: data.members.into_iter().map(|v| v as u64).collect(),
+                    event: Arc::new(event),
+                })
+            }
+            "message_pin_changed" => {
+                let data: MessagePinChanged = serde_json::from_str(payload)?;
+                let event = match data.op.as_str() {
+                    "INSERT" => AppEvent::MessagePinned(data.pin),
+                    "DELETE" => AppEvent::MessageUnpinned(data.pin),
+                    _ => return Err(anyhow::anyhow!("Invalid operation: {}", data.op)),
+                };
+                Ok(Self {
+                    affect_users: data.members.into_iter().map(|v| v as u64).collect(),
+                    event: Arc::new(event),
+                })
+            }
+            "poll_updated" => {
+                let data: PollChanged = serde_json::from_str(payload)?;
+                Ok(Self {
+                    affect_users: data.members.into_iter().map(|v| v as u64).collect(),
+                    event: Arc::new(AppEvent::PollUpdated(data.poll)),
+                })
+            }
+            "message_mentioned" => {
+                let data: MessageMentioned = serde_json::from_str(payload)?;
+                let mention = data.mention;
+                Ok(Self {
+                    affect_users: HashSet::from([mention.user_id as u64]),
+                    event: Arc::new(AppEvent::Mentioned(Mention {
+                        id: mention.id,
+                        kind: mention.kind,
+                        created_at: mention.created_at,
+                        message: data.message,
+                    })),
+                })
+            }
+            "chat_read_updated" => {
+                let data: ReadReceipt = serde_json::from_str(payload)?;
+                Ok(Self {
+                    affect_users: HashSet::from([data.user_id as u64]),
+                    event: Arc::new(AppEvent::ReadUpdated(data)),
+                })
+            }
+            "chat_draft_updated" => {
+                let data: Draft = serde_json::from_str(payload)?;
+                Ok(Self {
+                    affect_users: HashSet::from([data.user_id as u64]),
+                    event: Arc::new(AppEvent::DraftUpdated(data)),
+                })
+            }
+            "saved_message_reminder" => {
+                let data: SavedReminder = serde_json::from_str(payload)?;
+                let saved = data.saved;
+                Ok(Self {
+                    affect_users: HashSet::from([saved.user_id as u64]),
+                    event: Arc::new(AppEvent::SavedMessageReminder(Reminder {
+                        id: saved.id,
+                        note: saved.note,
+                        remind_at: saved.remind_at,
+                        message: data.message,
+                    })),
+                })
+            }
             _ => Err(anyhow::anyhow!("Invalid channel: {}", channel)),
         }
     }
@@ -98,26 +322,64 @@ fn get_affected_chat_user_ids(old: Option<&Chat>, new: Option<&Chat>) -> HashSet
     }
 }
 
+/// Send the event to the live sessions of the users.
+pub(crate) fn send_event(
+    users: &UserMap,
+    user_ids: impl IntoIterator<Item = u64>,
+    event: Arc<AppEvent>,
+) {
+    for user_id in user_ids {
+        if let Some(entry) = users.get(&user_id) {
+            info!("Sending notification to user {}", user_id);
+            if let Err(e) = entry.value().send(event.clone()) {
+                warn!("Failed to send notification to user {}: {}", user_id, e);
+            }
+        }
+    }
+}
+
 pub async fn setup_pg_listener(state: AppState) -> anyhow::Result<()> {
     let mut listener = PgListener::connect(&state.config.server.db_url).await?;
     listener.listen("chat_updated").await?;
+    listener.listen("chat_member_changed").await?;
     listener.listen("chat_message_created").await?;
+    listener.listen("chat_message_updated").await?;
+    listener.listen("chat_message_deleted").await?;
+    listener.listen("message_reaction_changed").await?;
+    listener.listen("message_pin_changed").await?;
+    listener.listen("poll_updated").await?;
+    listener.listen("message_mentioned").await?;
+    listener.listen("chat_read_updated").await?;
+    listener.listen("chat_draft_updated").await?;
+    listener.listen("saved_message_reminder").await?;
 
     let mut stream = listener.into_stream();
 
     tokio::spawn(async move {
         while let Some(Ok(notif)) = stream.next().await {
             info!("Received notification: {:?}", notif);
-            let notification = Notification::load(notif.channel(), notif.payload())?;
-            info!("Notification: {:?}", notification);
-            let users = &state.users;
-            for user_id in notification.affect_users {
-                if let Some(entry) = users.get(&user_id) {
-                    info!("Sending notification to user {}", user_id);
-                    if let Err(e) = entry.value().send(notification.event.clone()) {
-                        warn!("Failed to send notification to user {}: {}", user_id, e);
-                    }
+            let notification = match Notification::load(notif.channel(), notif.payload()) {
+                Ok(notification) => notification,
+                Err(e) => {
+                    warn!("Failed to load notification: {}", e);
+                    continue;
                 }
+            };
+            info!("Notification: {:?}", notification);
+            let removed = match notification.event.as_ref() {
+                AppEvent::MemberRemoved(member) => Some((member.chat.id, member.user_id)),
+                _ => None,
+            };
+            // clients not yet listening to the member events still learn about the change
+            if let AppEvent::MemberAdded(member) | AppEvent::MemberRemoved(member) =
+                notification.event.as_ref()
+            {
+                let event = Arc::new(AppEvent::AddToChat(member.chat.clone()));
+                send_event(&state.users, notification.affect_users.clone(), event);
+            }
+            send_event(&state.users, notification.affect_users, notification.event);
+            if let Some((chat_id, user_id)) = removed {
+                remove_typing_member(&state, chat_id, user_id);
             }
         }
         Ok::<_, anyhow::Error>(())
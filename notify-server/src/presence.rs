@@ -0,0 +1,208 @@
+use crate::error::AppError;
+use crate::notify::{send_event, AppEvent};
+use crate::{AppState, UserMap};
+use axum::extract::{Query, State};
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use chat_core::User;
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{interval, Instant};
+use tracing::warn;
+
+/// A connected user without heartbeat within this duration is away.
+const AWAY_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PresenceStatus {
+    Online,
+    Away,
+    Offline,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Presence {
+    pub user_id: i64,
+    pub status: PresenceStatus,
+    pub last_seen_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PresenceQuery {
+    /// comma separated user ids
+    ids: String,
+}
+
+/// Mark the user as active, an away user becomes online again.
+pub(crate) async fn heartbeat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let (was_away, status) = match state.users.get_mut(&(user.id as u64)) {
+        Some(mut entry) => {
+            let was_away = entry.status == PresenceStatus::Away;
+            entry.touch();
+            (was_away, entry.status)
+        }
+        None => (false, PresenceStatus::Offline),
+    };
+    if was_away {
+        notify_presence(&state.users, user.ws_id, user.id, status, None);
+    }
+    Ok(Json(Presence {
+        user_id: user.id,
+        status,
+        last_seen_at: None,
+    }))
+}
+
+/// Get the presence of the users in the workspace of the current user.
+pub(crate) async fn get_presence_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(query): Query<PresenceQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let ids = parse_ids(&query.ids)?;
+    let rows: Vec<(i64, Option<DateTime<Utc>>)> = sqlx::query_as(
+        r#"
+        SELECT id, last_seen_at
+        FROM users
+        WHERE id = ANY($1) AND ws_id = $2
+        ORDER BY id
+        "#,
+    )
+    .bind(&ids)
+    .bind(user.ws_id)
+    .fetch_all(&state.pool)
+    .await?;
+
+    let presence: Vec<Presence> = rows
+        .into_iter()
+        .map(
+            |(user_id, last_seen_at)| match state.users.get(&(user_id as u64)) {
+                Some(entry) => Presence {
+                    user_id,
+                    status: entry.status,
+                    last_seen_at: None,
+                },
+                None => Presence {
+                    user_id,
+                    status: PresenceStatus::Offline,
+                    last_seen_at,
+                },
+            },
+        )
+        .collect();
+    Ok(Json(presence))
+}
+
+/// The user just opened the first session.
+pub(crate) fn user_online(state: &AppState, ws_id: i64, user_id: i64) {
+    notify_presence(&state.users, ws_id, user_id, PresenceStatus::Online, None);
+}
+
+/// The last session of the user is closed, persist when the user was last seen.
+/// Nothing happens if the user reconnected in the meantime.
+pub(crate) async fn user_offline(state: &AppState, ws_id: i64, user_id: i64) {
+    if is_connected(&state.users, user_id) {
+        return;
+    }
+    let now = Utc::now();
+    if let Err(e) = sqlx::query("UPDATE users SET last_seen_at = $1 WHERE id = $2")
+        .bind(now)
+        .bind(user_id)
+        .execute(&state.pool)
+        .await
+    {
+        warn!("Failed to update last seen of user {}: {}", user_id, e);
+    }
+    // the user may reconnect while last seen is written
+    if is_connected(&state.users, user_id) {
+        return;
+    }
+    notify_presence(
+        &state.users,
+        ws_id,
+        user_id,
+        PresenceStatus::Offline,
+        Some(now),
+    );
+}
+
+/// Periodically mark the users without recent heartbeat as away.
+pub fn setup_presence_sweeper(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = interval(SWEEP_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let now = Instant::now();
+            let mut away = vec![];
+            for mut entry in state.users.iter_mut() {
+                if entry.status == PresenceStatus::Online
+                    && now.duration_since(entry.last_active) >= AWAY_TIMEOUT
+                {
+                    entry.status = PresenceStatus::Away;
+                    away.push((entry.ws_id, *entry.key() as i64));
+                }
+            }
+            for (ws_id, user_id) in away {
+                notify_presence(&state.users, ws_id, user_id, PresenceStatus::Away, None);
+            }
+        }
+    });
+}
+
+/// Send the presence change to the other connected users of the workspace.
+fn notify_presence(
+    users: &UserMap,
+    ws_id: i64,
+    user_id: i64,
+    status: PresenceStatus,
+    last_seen_at: Option<DateTime<Utc>>,
+) {
+    let event = Arc::new(AppEvent::PresenceChanged(Presence {
+        user_id,
+        status,
+        last_seen_at,
+    }));
+    let ids: Vec<u64> = users
+        .iter()
+        .filter(|entry| entry.ws_id == ws_id && *entry.key() != user_id as u64)
+        .map(|entry| *entry.key())
+        .collect();
+    send_event(users, ids, event);
+}
+
+fn is_connected(users: &UserMap, user_id: i64) -> bool {
+    users
+        .get(&(user_id as u64))
+        .is_some_and(|entry| entry.cnt > 0)
+}
+
+fn parse_ids(ids: &str) -> Result<Vec<i64>, AppError> {
+    ids.split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            id.parse()
+                .map_err(|_| AppError::InvalidInput(format!("invalid user id: {id}")))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ids_should_work() {
+        assert_eq!(parse_ids("1, 2,3,").unwrap(), vec![1, 2, 3]);
+        assert!(parse_ids("").unwrap().is_empty());
+        assert!(parse_ids("1,x").is_err());
+    }
+}
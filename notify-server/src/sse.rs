@@ -1,4 +1,5 @@
 use crate::notify::AppEvent;
+use crate::presence::{user_offline, user_online};
 use crate::{AppState, SenderReceiverCnt};
 use axum::extract::State;
 use axum::response::{sse::Event, Sse};
@@ -25,15 +26,18 @@ impl Drop for ReceiverGuard {
         let user_id = self.user_id;
         tokio::spawn(async move {
             let users = &state.users;
-            let cnt = {
+            let ws_id = {
                 let mut entry = users.get_mut(&user_id).unwrap();
                 entry.reduce();
                 info!("User {} is leaving,current {} session", user_id, entry.cnt);
-                entry.cnt
+                entry.ws_id
             };
-            if cnt == 0 {
-                users.remove(&user_id);
+            if users
+                .remove_if(&user_id, |_, entry| entry.cnt == 0)
+                .is_some()
+            {
                 info!("User {} unsubscribed", &user_id);
+                user_offline(&state, ws_id, user_id as _).await;
             }
         });
     }
@@ -49,14 +53,20 @@ pub(crate) async fn sse_handler(
     let users = &state.users;
     let user_id = user.id as u64;
 
-    let mut entry = users.entry(user_id).or_insert_with(|| {
-        let (tx, _) = broadcast::channel(CHANNEL_CAPACITY);
-        SenderReceiverCnt::new(tx)
-    });
-    let tx = entry.clone();
-    let rx = tx.subscribe();
-    entry.increase();
-    info!("User {} subscribed {} times", user_id, entry.cnt);
+    let (rx, first_session) = {
+        let mut entry = users.entry(user_id).or_insert_with(|| {
+            let (tx, _) = broadcast::channel(CHANNEL_CAPACITY);
+            SenderReceiverCnt::new(tx, user.ws_id)
+        });
+        let rx = entry.subscribe();
+        entry.increase();
+        entry.touch();
+        info!("User {} subscribed {} times", user_id, entry.cnt);
+        (rx, entry.cnt == 1)
+    };
+    if first_session {
+        user_online(&state, user.ws_id, user.id);
+    }
 
     let guard = ReceiverGuard {
         state: state.clone(),
@@ -72,7 +82,24 @@ pub(crate) async fn sse_handler(
                 AppEvent::NewChat(_) => "NewChat",
                 AppEvent::AddToChat(_) => "AddToChat",
                 AppEvent::RemoveFromChat(_) => "RemoveFromChat",
+                AppEvent::ChatArchived(_) => "ChatArchived",
+                AppEvent::ChatUnarchived(_) => "ChatUnarchived",
+                AppEvent::MemberAdded(_) => "MemberAdded",
+                AppEvent::MemberRemoved(_) => "MemberRemoved",
                 AppEvent::NewMessage(_) => "NewMessage",
+                AppEvent::MessageUpdated(_) => "MessageUpdated",
+                AppEvent::MessageDeleted(_) => "MessageDeleted",
+                AppEvent::ReactionAdded(_) => "ReactionAdded",
+                AppEvent::ReactionRemoved(_) => "ReactionRemoved",
+                AppEvent::MessagePinned(_) => "MessagePinned",
+                AppEvent::MessageUnpinned(_) => "MessageUnpinned",
+                AppEvent::PollUpdated(_) => "PollUpdated",
+                AppEvent::Mentioned(_) => "Mentioned",
+                AppEvent::ReadUpdated(_) => "ReadUpdated",
+                AppEvent::DraftUpdated(_) => "DraftUpdated",
+                AppEvent::SavedMessageReminder(_) => "SavedMessageReminder",
+                AppEvent::Typing(_) => "Typing",
+                AppEvent::PresenceChanged(_) => "PresenceChanged",
                 AppEvent::ChatNameUpdate(_) => "ChatNameUpdate",
             };
             let data = serde_json::to_string(&v).expect("failed to serialize event");
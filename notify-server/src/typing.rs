@@ -0,0 +1,178 @@
+use crate::error::AppError;
+use crate::notify::{send_event, AppEvent};
+use crate::AppState;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Extension;
+use chat_core::User;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{interval, Instant};
+
+/// A typing indicator disappears if it is not refreshed within this duration.
+const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Typing {
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub typing: bool,
+}
+
+struct TypingEntry {
+    expires_at: Instant,
+    members: Vec<i64>,
+}
+
+/// Users currently typing, keyed by (chat id, user id).
+#[derive(Default)]
+pub struct TypingMap(DashMap<(i64, i64), TypingEntry>);
+
+impl TypingMap {
+    /// Start or refresh a typing indicator, returns true if the user just started typing.
+    fn start(&self, chat_id: i64, user_id: i64, members: Vec<i64>, now: Instant) -> bool {
+        let entry = TypingEntry {
+            expires_at: now + TYPING_TIMEOUT,
+            members,
+        };
+        self.0.insert((chat_id, user_id), entry).is_none()
+    }
+
+    /// Stop a typing indicator, returns the chat members to notify if the user was typing.
+    fn stop(&self, chat_id: i64, user_id: i64) -> Option<Vec<i64>> {
+        self.0
+            .remove(&(chat_id, user_id))
+            .map(|(_, entry)| entry.members)
+    }
+
+    /// Forget a member removed from the chat, returns the chat members to notify
+    /// if the user was typing.
+    fn remove_member(&self, chat_id: i64, user_id: i64) -> Option<Vec<i64>> {
+        let members = self.stop(chat_id, user_id);
+        for mut entry in self.0.iter_mut().filter(|entry| entry.key().0 == chat_id) {
+            entry.members.retain(|id| *id != user_id);
+        }
+        members.map(|members| members.into_iter().filter(|id| *id != user_id).collect())
+    }
+
+    /// Remove the indicators which are not refreshed in time.
+    fn expire(&self, now: Instant) -> Vec<(i64, i64, Vec<i64>)> {
+        let expired: Vec<_> = self
+            .0
+            .iter()
+            .filter(|entry| entry.expires_at <= now)
+            .map(|entry| *entry.key())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|(chat_id, user_id)| {
+                let (_, entry) = self
+                    .0
+                    .remove_if(&(chat_id, user_id), |_, entry| entry.expires_at <= now)?;
+                Some((chat_id, user_id, entry.members))
+            })
+            .collect()
+    }
+}
+
+pub(crate) async fn typing_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let members = state.fetch_chat_members(chat_id, user.id).await?;
+    if state
+        .typing
+        .start(chat_id, user.id, members.clone(), Instant::now())
+    {
+        notify_typing(&state, chat_id, user.id, &members, true);
+    }
+    Ok(StatusCode::OK)
+}
+
+pub(crate) async fn stop_typing_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    if let Some(members) = state.typing.stop(chat_id, user.id) {
+        notify_typing(&state, chat_id, user.id, &members, false);
+    }
+    Ok(StatusCode::OK)
+}
+
+/// Periodically clear the stale typing indicators.
+pub fn setup_typing_sweeper(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = interval(SWEEP_INTERVAL);
+        loop {
+            ticker.tick().await;
+            for (chat_id, user_id, members) in state.typing.expire(Instant::now()) {
+                notify_typing(&state, chat_id, user_id, &members, false);
+            }
+        }
+    });
+}
+
+/// Clear the typing indicators of a member removed from the chat,
+/// so that the member no longer receives typing events of the chat.
+pub(crate) fn remove_typing_member(state: &AppState, chat_id: i64, user_id: i64) {
+    if let Some(members) = state.typing.remove_member(chat_id, user_id) {
+        notify_typing(state, chat_id, user_id, &members, false);
+    }
+}
+
+fn notify_typing(state: &AppState, chat_id: i64, user_id: i64, members: &[i64], typing: bool) {
+    let event = Arc::new(AppEvent::Typing(Typing {
+        chat_id,
+        user_id,
+        typing,
+    }));
+    let users = members
+        .iter()
+        .filter(|id| **id != user_id)
+        .map(|id| *id as u64);
+    send_event(&state.users, users, event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typing_should_expire_unless_refreshed() {
+        let typing = TypingMap::default();
+        let now = Instant::now();
+        assert!(typing.start(1, 1, vec![1, 2], now));
+        assert!(typing.start(1, 2, vec![1, 2], now));
+        // refreshing does not start a new indicator
+        assert!(!typing.start(1, 1, vec![1, 2], now + Duration::from_secs(3)));
+
+        let expired = typing.expire(now + TYPING_TIMEOUT);
+        assert_eq!(expired, vec![(1, 2, vec![1, 2])]);
+
+        assert_eq!(typing.stop(1, 1), Some(vec![1, 2]));
+        assert_eq!(typing.stop(1, 1), None);
+        assert!(typing.expire(now + TYPING_TIMEOUT * 2).is_empty());
+    }
+
+    #[test]
+    fn removed_member_should_stop_receiving_typing() {
+        let typing = TypingMap::default();
+        let now = Instant::now();
+        typing.start(1, 1, vec![1, 2, 3], now);
+        typing.start(1, 2, vec![1, 2, 3], now);
+        typing.start(2, 1, vec![1, 2], now);
+
+        assert_eq!(typing.remove_member(1, 2), Some(vec![1, 3]));
+        assert_eq!(typing.remove_member(1, 2), None);
+        assert_eq!(typing.stop(1, 1), Some(vec![1, 3]));
+        // other chats are not affected
+        assert_eq!(typing.stop(2, 1), Some(vec![1, 2]));
+    }
+}